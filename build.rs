@@ -0,0 +1,74 @@
+use std::fs;
+use std::process::Command;
+
+/// Sets the build information printed by `--version`.
+///
+/// Values that cannot be determined, e.g. when building from a source archive
+/// without git, are set to `unknown`.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    set_env("GIT_COMMIT", git(&["rev-parse", "--short", "HEAD"]));
+    set_env(
+        "GIT_COMMIT_DATE",
+        git(&["log", "-1", "--format=%cd", "--date=short"]),
+    );
+    set_env("LIBP2P_VERSION", libp2p_version());
+
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/rendezvous.proto");
+        tonic_build::compile_protos("proto/rendezvous.proto")
+            .expect("Failed to compile proto/rendezvous.proto");
+    }
+}
+
+fn set_env(name: &str, value: Option<String>) {
+    println!(
+        "cargo:rustc-env={}={}",
+        name,
+        value.unwrap_or_else(|| "unknown".to_owned())
+    );
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+    if value.is_empty() {
+        return None;
+    }
+
+    Some(value)
+}
+
+/// Reads the version and source of the libp2p package from the lock file.
+///
+/// The libp2p dependency is a git fork, so the commit it is pinned to is
+/// appended to the version.
+fn libp2p_version() -> Option<String> {
+    let lock = fs::read_to_string("Cargo.lock").ok()?;
+    let package = lock
+        .split("[[package]]")
+        .find(|package| package.contains("\nname = \"libp2p\"\n"))?;
+
+    let field = |name: &str| {
+        package.lines().find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(" = \"")?
+                .strip_suffix('"')
+                .map(str::to_owned)
+        })
+    };
+
+    let version = field("version")?;
+    match field("source").and_then(|source| Some(source.split_once('#')?.1.to_owned())) {
+        Some(commit) => Some(format!("{} ({})", version, &commit[..commit.len().min(8)])),
+        None => Some(version),
+    }
+}
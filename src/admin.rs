@@ -0,0 +1,509 @@
+use crate::audit::AuditLog;
+use crate::connections::Connections;
+use crate::registrations::{unix_timestamp, Registrations};
+use anyhow::{Context, Result};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tokio::sync::{mpsc, oneshot};
+
+/// Registration removed through the admin API. The event loop disconnects the
+/// peer and stops tracking the registration.
+#[derive(Debug)]
+pub struct Eviction {
+    pub peer: PeerId,
+    pub namespace: String,
+}
+
+/// Request to replace the listener on `from` with a listener on `to`. The
+/// event loop replies once the new listener is started and removes the old
+/// listener as soon as the new one reports a listen address.
+#[derive(Debug)]
+pub struct ListenerReplacement {
+    pub from: Multiaddr,
+    pub to: Multiaddr,
+    pub reply: oneshot::Sender<Result<(), String>>,
+}
+
+/// Version and capabilities of the server, served on `GET /info`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Info {
+    pub version: &'static str,
+    pub peer_id: String,
+    /// Protocol version announced through identify
+    pub protocol_version: &'static str,
+    pub rendezvous: RendezvousInfo,
+    pub protocols: ProtocolsInfo,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RendezvousInfo {
+    pub protocol: &'static str,
+    /// Configured TTL bounds, the defaults of the rendezvous behaviour apply
+    /// if `None`
+    pub min_ttl: Option<u64>,
+    pub max_ttl: Option<u64>,
+}
+
+/// Behaviours composed next to rendezvous. AutoNAT and relay are never
+/// composed and always reported as disabled.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolsInfo {
+    pub ping: bool,
+    pub identify: bool,
+    pub kademlia: bool,
+    pub autonat: bool,
+    pub relay: bool,
+}
+
+/// State shared between the swarm event loop and the admin server.
+pub struct Admin {
+    token: String,
+    info: Info,
+    registrations: Arc<RwLock<Registrations>>,
+    connections: Arc<RwLock<Connections>>,
+    audit_log: Arc<RwLock<AuditLog>>,
+    evictions: mpsc::UnboundedSender<Eviction>,
+    replacements: mpsc::UnboundedSender<ListenerReplacement>,
+}
+
+impl Admin {
+    pub fn new(
+        token: String,
+        info: Info,
+        registrations: Arc<RwLock<Registrations>>,
+        connections: Arc<RwLock<Connections>>,
+        audit_log: Arc<RwLock<AuditLog>>,
+        evictions: mpsc::UnboundedSender<Eviction>,
+        replacements: mpsc::UnboundedSender<ListenerReplacement>,
+    ) -> Self {
+        Self {
+            token,
+            info,
+            registrations,
+            connections,
+            audit_log,
+            evictions,
+            replacements,
+        }
+    }
+
+    /// Checks the `Authorization: Bearer <token>` header of `request` in
+    /// constant time.
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        let token = match request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            Some(token) => token,
+            None => return false,
+        };
+
+        ring::constant_time::verify_slices_are_equal(token.as_bytes(), self.token.as_bytes())
+            .is_ok()
+    }
+
+    fn registrations(&self) -> Vec<RegistrationEntry> {
+        let registrations = self
+            .registrations
+            .read()
+            .expect("registrations are not updated after a panic");
+        let now = unix_timestamp();
+
+        registrations
+            .active(now)
+            .map(|registration| RegistrationEntry {
+                peer_id: registration.peer_id.clone(),
+                namespace: registration.namespace.clone(),
+                addresses: registration.addresses.clone(),
+                ttl: registration.remaining_ttl(now),
+                restored: registration.restored,
+            })
+            .collect()
+    }
+
+    fn namespaces(&self) -> BTreeMap<String, NamespaceEntry> {
+        let registrations = self
+            .registrations
+            .read()
+            .expect("registrations are not updated after a panic");
+        let mut namespaces = BTreeMap::<String, NamespaceEntry>::new();
+
+        for registration in registrations.active(unix_timestamp()) {
+            let entry = namespaces
+                .entry(registration.namespace.clone())
+                .or_default();
+            entry.registrations += 1;
+            if registration.restored {
+                entry.restored += 1;
+            }
+        }
+
+        namespaces
+    }
+
+    /// Returns the connected peers with their open connections and the
+    /// namespaces they are registered in. Restored registrations are listed
+    /// separately, the peer has not registered them with this server.
+    fn connections(&self) -> Vec<ConnectionEntry> {
+        let now = unix_timestamp();
+        let mut namespaces = HashMap::<String, BTreeSet<String>>::new();
+        let mut restored_namespaces = HashMap::<String, BTreeSet<String>>::new();
+        for registration in self
+            .registrations
+            .read()
+            .expect("registrations are not updated after a panic")
+            .active(now)
+        {
+            let by_peer = if registration.restored {
+                &mut restored_namespaces
+            } else {
+                &mut namespaces
+            };
+            by_peer
+                .entry(registration.peer_id.clone())
+                .or_default()
+                .insert(registration.namespace.clone());
+        }
+
+        let connections = self
+            .connections
+            .read()
+            .expect("connections are not updated after a panic");
+        let mut entries = connections
+            .iter()
+            .map(|(peer, connections)| {
+                let peer_id = peer.to_string();
+                let namespaces = namespaces.remove(&peer_id).unwrap_or_default();
+                let restored_namespaces = restored_namespaces.remove(&peer_id).unwrap_or_default();
+                let connected_since = connections
+                    .iter()
+                    .map(|connection| connection.established)
+                    .min()
+                    .unwrap_or(now);
+
+                ConnectionEntry {
+                    peer_id,
+                    connections: connections.len(),
+                    addresses: connections
+                        .iter()
+                        .map(|connection| connection.endpoint.get_remote_address().clone())
+                        .collect(),
+                    connected_secs: now.saturating_sub(connected_since),
+                    registered: !namespaces.is_empty(),
+                    namespaces: namespaces.into_iter().collect(),
+                    restored_namespaces: restored_namespaces.into_iter().collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+
+        entries
+    }
+
+    /// Returns the registrations in `namespace` with the signed peer records
+    /// the peers registered with.
+    fn records(&self, namespace: &str) -> Vec<RecordEntry> {
+        let registrations = self
+            .registrations
+            .read()
+            .expect("registrations are not updated after a panic");
+        let now = unix_timestamp();
+
+        registrations
+            .active(now)
+            .filter(|registration| registration.namespace == namespace)
+            .map(|registration| RecordEntry {
+                peer_id: registration.peer_id.clone(),
+                namespace: registration.namespace.clone(),
+                ttl: registration.remaining_ttl(now),
+                signed: registration.signed_record.is_some(),
+                signed_record: registration.signed_record.clone(),
+                addresses: registration.addresses.clone(),
+                restored: registration.restored,
+            })
+            .collect()
+    }
+
+    /// Removes all registrations matching `predicate` from the tracked state
+    /// and hands them to the event loop to disconnect the peers.
+    fn evict(&self, predicate: impl FnMut(&PeerId, &str) -> bool) -> Vec<EvictedEntry> {
+        let evicted = self
+            .registrations
+            .write()
+            .expect("registrations are not updated after a panic")
+            .evict(predicate);
+
+        evicted
+            .into_iter()
+            .map(|(peer, namespace)| {
+                tracing::info!(%peer, %namespace, "Evicted registration through admin API");
+
+                if self
+                    .evictions
+                    .send(Eviction {
+                        peer,
+                        namespace: namespace.clone(),
+                    })
+                    .is_err()
+                {
+                    tracing::debug!(%peer, "Event loop stopped, not disconnecting evicted peer");
+                }
+
+                EvictedEntry {
+                    peer_id: peer.to_string(),
+                    namespace,
+                }
+            })
+            .collect()
+    }
+
+    /// Hands the replacement of the listener on `from` to the event loop and
+    /// waits until the listener on `to` is started.
+    async fn replace_listener(&self, from: Multiaddr, to: Multiaddr) -> Result<(), String> {
+        let (reply, result) = oneshot::channel();
+        self.replacements
+            .send(ListenerReplacement { from, to, reply })
+            .map_err(|_| "Event loop stopped".to_owned())?;
+
+        result.await.map_err(|_| "Event loop stopped".to_owned())?
+    }
+}
+
+#[derive(Serialize)]
+struct RegistrationEntry {
+    peer_id: String,
+    namespace: String,
+    addresses: Vec<Multiaddr>,
+    /// Remaining TTL in seconds
+    ttl: u64,
+    /// Whether the registration was restored from the state file or mirrored.
+    /// The server does not return it to discoverers until the peer registers
+    /// again.
+    restored: bool,
+}
+
+/// Number of active registrations in a namespace
+#[derive(Default, Serialize)]
+struct NamespaceEntry {
+    registrations: usize,
+    /// Number of registrations that were restored from the state file or
+    /// mirrored, included in `registrations`
+    restored: usize,
+}
+
+/// Registration with the signed peer record, which consumers can verify
+/// themselves.
+#[derive(Serialize)]
+struct RecordEntry {
+    peer_id: String,
+    namespace: String,
+    /// Remaining TTL in seconds
+    ttl: u64,
+    /// Whether `signed_record` is set. Registrations restored from state
+    /// files of earlier versions and mirrored registrations only have the
+    /// unverified `addresses`.
+    signed: bool,
+    /// Base64 encoded protobuf encoding of the signed envelope containing
+    /// the peer record
+    signed_record: Option<String>,
+    addresses: Vec<Multiaddr>,
+    /// Whether the registration was restored from the state file or mirrored
+    restored: bool,
+}
+
+#[derive(Serialize)]
+struct ConnectionEntry {
+    peer_id: String,
+    /// Number of open connections
+    connections: usize,
+    /// Remote addresses of the open connections
+    addresses: Vec<Multiaddr>,
+    /// Seconds since the oldest open connection was established
+    connected_secs: u64,
+    /// Whether the peer has an active registration with this server
+    registered: bool,
+    namespaces: Vec<String>,
+    /// Namespaces of restored or mirrored registrations of the peer, which it
+    /// has not registered with this server yet
+    restored_namespaces: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct EvictedEntry {
+    peer_id: String,
+    namespace: String,
+}
+
+#[derive(Deserialize)]
+struct ReplaceListenerRequest {
+    from: String,
+    to: String,
+}
+
+/// Binds an HTTP server to `address` and serves the active registrations on
+/// `/registrations` and the number of registrations per namespace on
+/// `/namespaces` in a separate task.
+///
+/// Registrations are evicted with `DELETE /registrations/{peer_id}` and
+/// `DELETE /namespaces/{namespace}`, which disconnect the affected peers and
+/// respond with the evicted registrations. The rendezvous behaviour does not
+/// allow removing registrations from its store, so evicted registrations stay
+/// discoverable until their TTL expires unless the peer unregisters.
+///
+/// `GET /audit` returns the most recent rendezvous events, see
+/// [`AuditLog`].
+///
+/// `GET /namespaces/{namespace}/records` returns the registrations in a
+/// namespace with the signed peer records the peers registered with, so
+/// consumers can verify them.
+///
+/// `GET /info` returns the version, peer id and enabled protocols, see
+/// [`Info`].
+///
+/// `GET /connections` returns the connected peers with the remote addresses
+/// of their connections, how long they are connected and whether they have an
+/// active registration.
+///
+/// `POST /listeners/replace` with a JSON body like `{"from":
+/// "/ip4/0.0.0.0/tcp/8888", "to": "/ip4/0.0.0.0/tcp/9999"}` starts listening
+/// on `to` and stops listening on `from` once the new listener is up.
+/// Established connections are kept. `from` is the address the listener was
+/// started on, e.g. through `--listen-tcp`, not one of the addresses it
+/// reported. The request is answered with `202 Accepted` once the new listener
+/// is started.
+///
+/// All requests have to carry the admin token as bearer token, otherwise they
+/// are answered with `401 Unauthorized`.
+pub fn spawn_server(address: SocketAddr, admin: Arc<Admin>) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let admin = admin.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let admin = admin.clone();
+
+                async move { Ok::<_, Infallible>(handle(request, &admin).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .with_context(|| format!("Failed to bind admin server to {}", address))?
+        .serve(make_service);
+
+    tracing::info!(%address, "Serving admin API");
+
+    tokio::spawn(async move {
+        if let Err(error) = server.await {
+            tracing::error!(%error, "Admin server failed");
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle(request: Request<Body>, admin: &Admin) -> Response<Body> {
+    if !admin.is_authorized(&request) {
+        let mut response = status(StatusCode::UNAUTHORIZED);
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+
+        return response;
+    }
+
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/info") => json(&admin.info),
+        (&Method::GET, "/registrations") => json(&admin.registrations()),
+        (&Method::GET, "/namespaces") => json(&admin.namespaces()),
+        (&Method::GET, "/connections") => json(&admin.connections()),
+        (&Method::GET, "/audit") => json(
+            &admin
+                .audit_log
+                .read()
+                .expect("audit log is not updated after a panic")
+                .entries(),
+        ),
+        (&Method::GET, path) => match path
+            .strip_prefix("/namespaces/")
+            .and_then(|path| path.strip_suffix("/records"))
+        {
+            Some(namespace) => json(&admin.records(namespace)),
+            None => status(StatusCode::NOT_FOUND),
+        },
+        (&Method::POST, "/listeners/replace") => replace_listener(request, admin).await,
+        (&Method::DELETE, path) => {
+            if let Some(peer) = path.strip_prefix("/registrations/") {
+                match peer.parse::<PeerId>() {
+                    Ok(peer) => json(&admin.evict(|registered, _| *registered == peer)),
+                    Err(_) => status(StatusCode::BAD_REQUEST),
+                }
+            } else if let Some(namespace) = path.strip_prefix("/namespaces/") {
+                json(&admin.evict(|_, registered| registered == namespace))
+            } else {
+                status(StatusCode::NOT_FOUND)
+            }
+        }
+        _ => status(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn replace_listener(request: Request<Body>, admin: &Admin) -> Response<Body> {
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(error) => return text(StatusCode::BAD_REQUEST, error.to_string()),
+    };
+    let request = match serde_json::from_slice::<ReplaceListenerRequest>(&body) {
+        Ok(request) => request,
+        Err(error) => return text(StatusCode::BAD_REQUEST, error.to_string()),
+    };
+    let (from, to) = match (request.from.parse(), request.to.parse()) {
+        (Ok(from), Ok(to)) => (from, to),
+        _ => return text(StatusCode::BAD_REQUEST, "Invalid multiaddress".to_owned()),
+    };
+
+    match admin.replace_listener(from, to).await {
+        Ok(()) => status(StatusCode::ACCEPTED),
+        Err(error) => text(StatusCode::BAD_REQUEST, error),
+    }
+}
+
+fn json(body: &impl Serialize) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => {
+            let mut response = Response::new(Body::from(body));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+            response
+        }
+        Err(error) => {
+            tracing::error!(%error, "Failed to encode admin response");
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn text(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+
+    response
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+
+    response
+}
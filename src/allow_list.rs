@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use libp2p::PeerId;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::fs;
+
+/// Peers allowed to register, read from a file with one peer id per line.
+///
+/// Empty lines and lines starting with `#` are ignored, invalid peer ids are
+/// logged and skipped. The peers are shared with the [`Policy`] enforcing
+/// them, so reloading the file takes effect for the next request. Accepted
+/// registrations of peers removed from the file stay until they expire.
+///
+/// [`Policy`]: crate::Policy
+pub struct AllowList {
+    path: PathBuf,
+    peers: Arc<RwLock<HashSet<PeerId>>>,
+    modified: Option<SystemTime>,
+}
+
+impl AllowList {
+    pub async fn load(path: PathBuf) -> Result<Self> {
+        let mut allow_list = Self {
+            path,
+            peers: Arc::default(),
+            modified: None,
+        };
+        allow_list.reload().await?;
+
+        Ok(allow_list)
+    }
+
+    pub fn peers(&self) -> Arc<RwLock<HashSet<PeerId>>> {
+        self.peers.clone()
+    }
+
+    /// Re-reads the file and replaces the allowed peers.
+    pub async fn reload(&mut self) -> Result<()> {
+        let modified = fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let content = fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("Failed to read allow peer file {}", self.path.display()))?;
+
+        let peers = parse(&content);
+        tracing::info!(path=%self.path.display(), count=peers.len(), "Loaded allowed peers");
+
+        *self
+            .peers
+            .write()
+            .expect("allowed peers are not updated after a panic") = peers;
+        self.modified = modified;
+
+        Ok(())
+    }
+
+    /// Re-reads the file if its modification time changed since it was last
+    /// read.
+    pub async fn reload_if_modified(&mut self) -> Result<()> {
+        let modified = fs::metadata(&self.path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Failed to read allow peer file {}", self.path.display()))?;
+
+        if self.modified == Some(modified) {
+            return Ok(());
+        }
+
+        self.reload().await
+    }
+}
+
+fn parse(content: &str) -> HashSet<PeerId> {
+    let mut peers = HashSet::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.parse::<PeerId>() {
+            Ok(peer) => {
+                peers.insert(peer);
+            }
+            Err(_) => {
+                tracing::warn!(line = index + 1, content=%line, "Skipping invalid peer id in allow peer file")
+            }
+        }
+    }
+
+    peers
+}
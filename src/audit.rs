@@ -0,0 +1,73 @@
+use crate::registrations::unix_timestamp;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// The most recent rendezvous events, kept in memory for debugging through
+/// the admin API.
+///
+/// Once `capacity` entries are recorded, the oldest entry is dropped for each
+/// new one.
+#[derive(Debug)]
+pub struct AuditLog {
+    capacity: usize,
+    entries: VecDeque<AuditEntry>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Registered,
+    Unregistered,
+    Expired,
+    DiscoverServed,
+    Evicted,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch at which the event was handled
+    timestamp: u64,
+    #[serde(rename = "type")]
+    kind: Kind,
+    peer_id: String,
+    namespace: Option<String>,
+    addresses: Vec<Multiaddr>,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        kind: Kind,
+        peer: PeerId,
+        namespace: Option<String>,
+        addresses: Vec<Multiaddr>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(AuditEntry {
+            timestamp: unix_timestamp(),
+            kind,
+            peer_id: peer.to_string(),
+            namespace,
+            addresses,
+        });
+    }
+
+    /// Returns the entries from oldest to newest.
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
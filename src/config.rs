@@ -0,0 +1,350 @@
+use crate::{parse_multiaddr, parse_namespace, Cli, SecretFormat};
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use libp2p::PeerId;
+use rendezvous_server::{Muxer, NoisePattern};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::level_filters::LevelFilter;
+
+/// Settings loaded from a TOML config file through `--config`.
+///
+/// The keys mirror the fields of [`Cli`]. Values provided on the command line
+/// take precedence over values from the file. Switches are or-ed instead,
+/// [`Cli`] cannot tell an omitted switch from one that is turned off.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    secret_file: Option<PathBuf>,
+    secret_env: Option<String>,
+    secret_format: Option<String>,
+    listen_tcp: Option<u16>,
+    listen_tcp_env: Option<String>,
+    listen_addresses: Option<Vec<String>>,
+    listen_memory: Option<u64>,
+    external_addresses: Option<Vec<String>>,
+    auto_external_address: Option<bool>,
+    upstream: Option<String>,
+    upstream_namespaces: Option<Vec<String>>,
+    listen_ip6: Option<bool>,
+    listen_retries: Option<u32>,
+    listen_retry_delay: Option<u64>,
+    startup_delay: Option<u64>,
+    no_dns: Option<bool>,
+    json: Option<bool>,
+    log_level: Option<String>,
+    log_libp2p: Option<String>,
+    no_timestamp: Option<bool>,
+    log_file: Option<PathBuf>,
+    log_max_size: Option<u64>,
+    log_max_files: Option<usize>,
+    log_also_stderr: Option<bool>,
+    quiet: Option<bool>,
+    registration_log_throttle: Option<u64>,
+    ping: Option<bool>,
+    ping_interval: Option<u64>,
+    ping_keepalive: Option<bool>,
+    keep_alive: Option<u64>,
+    identify: Option<bool>,
+    kademlia: Option<bool>,
+    listen_websocket: Option<u16>,
+    listen_websocket_env: Option<String>,
+    tls_private_keys: Option<Vec<PathBuf>>,
+    tls_certificates: Option<Vec<PathBuf>>,
+    tls_bundle: Option<PathBuf>,
+    allow_expired_cert: Option<bool>,
+    cert_expiry_warning_days: Option<u64>,
+    min_ttl: Option<u64>,
+    max_ttl: Option<u64>,
+    allowed_namespaces: Option<Vec<String>>,
+    allowed_peers: Option<Vec<String>>,
+    allow_peer_file: Option<PathBuf>,
+    denied_peers: Option<Vec<String>>,
+    max_namespaces_per_peer: Option<usize>,
+    keep_connection_over_namespace_cap: Option<bool>,
+    max_total_registrations: Option<usize>,
+    evict_on_full: Option<bool>,
+    reject_private_addresses: Option<bool>,
+    allow_partially_routable: Option<bool>,
+    max_addresses_per_registration: Option<usize>,
+    require_identify: Option<bool>,
+    require_agent_prefix: Option<String>,
+    denied_cidrs: Option<Vec<String>>,
+    max_discover_per_minute: Option<u32>,
+    max_reconnects_per_minute: Option<u32>,
+    ban_duration: Option<u64>,
+    handshake_timeout: Option<u64>,
+    dial_timeout: Option<u64>,
+    muxer: Option<String>,
+    noise_pattern: Option<String>,
+    yamux_max_buffer_size: Option<usize>,
+    yamux_receive_window: Option<u32>,
+    tcp_backlog: Option<u32>,
+    tcp_nodelay: Option<bool>,
+    tcp_reuse_port: Option<bool>,
+    max_established_connections: Option<u32>,
+    max_pending_connections: Option<u32>,
+    max_concurrent_dials: Option<u32>,
+    metrics_addr: Option<SocketAddr>,
+    metrics_max_namespaces: Option<usize>,
+    health_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
+    admin_token: Option<String>,
+    max_connection_age: Option<u64>,
+    exempt_registered: Option<bool>,
+    mirror_to: Option<SocketAddr>,
+    mirror_from: Option<SocketAddr>,
+    mirror_secret: Option<String>,
+    grpc_addr: Option<SocketAddr>,
+    self_test: Option<bool>,
+    self_test_fatal: Option<bool>,
+    state_file: Option<PathBuf>,
+    summary_interval: Option<u64>,
+    event_buffer: Option<usize>,
+    audit_buffer_size: Option<usize>,
+    // only known on unix, unknown keys are rejected elsewhere
+    #[cfg(unix)]
+    event_socket: Option<PathBuf>,
+    #[cfg(unix)]
+    control_socket: Option<PathBuf>,
+    registration_log: Option<PathBuf>,
+    registration_log_max_size: Option<u64>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let config = toml::from_str(&content)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// Fills in all settings of `cli` that were not provided on the command
+    /// line.
+    pub fn merge_into(self, cli: &mut Cli) -> Result<()> {
+        merge(&mut cli.secret_file, self.secret_file);
+        merge(&mut cli.secret_env, self.secret_env);
+        merge(
+            &mut cli.secret_format,
+            self.secret_format
+                .as_deref()
+                .map(SecretFormat::from_str)
+                .transpose()?,
+        );
+        // a port from the command line overrides both settings of the file
+        if cli.listen_tcp.is_none() && cli.listen_tcp_env.is_none() {
+            cli.listen_tcp = self.listen_tcp;
+            cli.listen_tcp_env = self.listen_tcp_env;
+        }
+        merge_vec(
+            &mut cli.listen_addresses,
+            parse_all(self.listen_addresses, parse_multiaddr)?,
+        );
+        merge(&mut cli.listen_memory, self.listen_memory);
+        merge_vec(
+            &mut cli.external_addresses,
+            parse_all(self.external_addresses, parse_multiaddr)?,
+        );
+        cli.auto_external_address |= self.auto_external_address.unwrap_or_default();
+        merge(
+            &mut cli.upstream,
+            self.upstream.as_deref().map(parse_multiaddr).transpose()?,
+        );
+        merge_vec(
+            &mut cli.upstream_namespaces,
+            parse_all(self.upstream_namespaces, parse_namespace)?,
+        );
+        cli.listen_ip6 |= self.listen_ip6.unwrap_or_default();
+        merge(&mut cli.listen_retries, self.listen_retries);
+        merge(&mut cli.listen_retry_delay, self.listen_retry_delay);
+        merge(&mut cli.startup_delay, self.startup_delay);
+        cli.no_dns |= self.no_dns.unwrap_or_default();
+        cli.json |= self.json.unwrap_or_default();
+        merge(
+            &mut cli.log_level,
+            self.log_level.as_deref().map(parse_log_level).transpose()?,
+        );
+        merge(
+            &mut cli.log_libp2p,
+            self.log_libp2p
+                .as_deref()
+                .map(parse_log_level)
+                .transpose()?,
+        );
+        cli.no_timestamp |= self.no_timestamp.unwrap_or_default();
+        merge(&mut cli.log_file, self.log_file);
+        merge(&mut cli.log_max_size, self.log_max_size);
+        merge(&mut cli.log_max_files, self.log_max_files);
+        cli.log_also_stderr |= self.log_also_stderr.unwrap_or_default();
+        cli.quiet |= self.quiet.unwrap_or_default();
+        merge(
+            &mut cli.registration_log_throttle,
+            self.registration_log_throttle,
+        );
+        cli.ping |= self.ping.unwrap_or_default();
+        merge(&mut cli.ping_interval, self.ping_interval);
+        cli.ping_keepalive |= self.ping_keepalive.unwrap_or_default();
+        merge(&mut cli.keep_alive, self.keep_alive);
+        cli.identify |= self.identify.unwrap_or_default();
+        cli.kademlia |= self.kademlia.unwrap_or_default();
+        if cli.listen_websocket.is_none() && cli.listen_websocket_env.is_none() {
+            cli.listen_websocket = self.listen_websocket;
+            cli.listen_websocket_env = self.listen_websocket_env;
+        }
+        merge_vec(
+            &mut cli.tls_private_keys,
+            self.tls_private_keys.unwrap_or_default(),
+        );
+        merge_vec(
+            &mut cli.tls_certificates,
+            self.tls_certificates.unwrap_or_default(),
+        );
+        merge(&mut cli.tls_bundle, self.tls_bundle);
+        cli.allow_expired_cert |= self.allow_expired_cert.unwrap_or_default();
+        merge(
+            &mut cli.cert_expiry_warning_days,
+            self.cert_expiry_warning_days,
+        );
+        merge(&mut cli.min_ttl, self.min_ttl);
+        merge(&mut cli.max_ttl, self.max_ttl);
+        merge_vec(
+            &mut cli.allowed_namespaces,
+            parse_all(self.allowed_namespaces, parse_namespace)?,
+        );
+        merge_vec(
+            &mut cli.allowed_peers,
+            parse_all(self.allowed_peers, parse_peer_id)?,
+        );
+        merge(&mut cli.allow_peer_file, self.allow_peer_file);
+        merge_vec(
+            &mut cli.denied_peers,
+            parse_all(self.denied_peers, parse_peer_id)?,
+        );
+        merge(
+            &mut cli.max_namespaces_per_peer,
+            self.max_namespaces_per_peer,
+        );
+        cli.keep_connection_over_namespace_cap |=
+            self.keep_connection_over_namespace_cap.unwrap_or_default();
+        merge(
+            &mut cli.max_total_registrations,
+            self.max_total_registrations,
+        );
+        cli.evict_on_full |= self.evict_on_full.unwrap_or_default();
+        cli.reject_private_addresses |= self.reject_private_addresses.unwrap_or_default();
+        cli.allow_partially_routable |= self.allow_partially_routable.unwrap_or_default();
+        merge(
+            &mut cli.max_addresses_per_registration,
+            self.max_addresses_per_registration,
+        );
+        cli.require_identify |= self.require_identify.unwrap_or_default();
+        merge(&mut cli.require_agent_prefix, self.require_agent_prefix);
+        merge_vec(
+            &mut cli.denied_cidrs,
+            parse_all(self.denied_cidrs, parse_cidr)?,
+        );
+        merge(
+            &mut cli.max_discover_per_minute,
+            self.max_discover_per_minute,
+        );
+        merge(
+            &mut cli.max_reconnects_per_minute,
+            self.max_reconnects_per_minute,
+        );
+        merge(&mut cli.ban_duration, self.ban_duration);
+        merge(&mut cli.handshake_timeout, self.handshake_timeout);
+        merge(&mut cli.dial_timeout, self.dial_timeout);
+        merge(
+            &mut cli.muxer,
+            self.muxer.as_deref().map(Muxer::from_str).transpose()?,
+        );
+        merge(
+            &mut cli.noise_pattern,
+            self.noise_pattern
+                .as_deref()
+                .map(NoisePattern::from_str)
+                .transpose()?,
+        );
+        merge(&mut cli.yamux_max_buffer_size, self.yamux_max_buffer_size);
+        merge(&mut cli.yamux_receive_window, self.yamux_receive_window);
+        merge(&mut cli.tcp_backlog, self.tcp_backlog);
+        merge(&mut cli.tcp_nodelay, self.tcp_nodelay);
+        cli.tcp_reuse_port |= self.tcp_reuse_port.unwrap_or_default();
+        merge(
+            &mut cli.max_established_connections,
+            self.max_established_connections,
+        );
+        merge(
+            &mut cli.max_pending_connections,
+            self.max_pending_connections,
+        );
+        merge(&mut cli.max_concurrent_dials, self.max_concurrent_dials);
+        merge(&mut cli.metrics_addr, self.metrics_addr);
+        merge(&mut cli.metrics_max_namespaces, self.metrics_max_namespaces);
+        merge(&mut cli.health_addr, self.health_addr);
+        merge(&mut cli.admin_addr, self.admin_addr);
+        merge(&mut cli.admin_token, self.admin_token);
+        merge(&mut cli.max_connection_age, self.max_connection_age);
+        cli.exempt_registered |= self.exempt_registered.unwrap_or_default();
+        merge(&mut cli.mirror_to, self.mirror_to);
+        merge(&mut cli.mirror_from, self.mirror_from);
+        merge(&mut cli.mirror_secret, self.mirror_secret);
+        merge(&mut cli.grpc_addr, self.grpc_addr);
+        cli.self_test |= self.self_test.unwrap_or_default();
+        cli.self_test_fatal |= self.self_test_fatal.unwrap_or_default();
+        merge(&mut cli.state_file, self.state_file);
+        merge(&mut cli.summary_interval, self.summary_interval);
+        merge(&mut cli.event_buffer, self.event_buffer);
+        merge(&mut cli.audit_buffer_size, self.audit_buffer_size);
+        #[cfg(unix)]
+        merge(&mut cli.event_socket, self.event_socket);
+        #[cfg(unix)]
+        merge(&mut cli.control_socket, self.control_socket);
+        merge(&mut cli.registration_log, self.registration_log);
+        merge(
+            &mut cli.registration_log_max_size,
+            self.registration_log_max_size,
+        );
+
+        Ok(())
+    }
+}
+
+fn merge<T>(cli: &mut Option<T>, file: Option<T>) {
+    if cli.is_none() {
+        *cli = file;
+    }
+}
+
+fn merge_vec<T>(cli: &mut Vec<T>, file: Vec<T>) {
+    if cli.is_empty() {
+        *cli = file;
+    }
+}
+
+fn parse_all<T>(values: Option<Vec<String>>, parse: impl Fn(&str) -> Result<T>) -> Result<Vec<T>> {
+    values
+        .unwrap_or_default()
+        .iter()
+        .map(|value| parse(value))
+        .collect()
+}
+
+fn parse_peer_id(s: &str) -> Result<PeerId> {
+    s.parse()
+        .with_context(|| format!("Failed to parse peer id {}", s))
+}
+
+fn parse_log_level(s: &str) -> Result<LevelFilter> {
+    s.parse()
+        .with_context(|| format!("Failed to parse log level {}", s))
+}
+
+fn parse_cidr(s: &str) -> Result<IpNet> {
+    s.parse()
+        .with_context(|| format!("Failed to parse CIDR {}", s))
+}
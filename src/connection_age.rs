@@ -0,0 +1,84 @@
+use libp2p::core::ConnectedPoint;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Tracks when the open connections of each peer were established to recycle
+/// peers whose oldest connection exceeds a maximum age.
+#[derive(Debug)]
+pub struct ConnectionAges {
+    max_age: Duration,
+    connections: HashMap<PeerId, Vec<(ConnectedPoint, Instant)>>,
+}
+
+impl ConnectionAges {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            connections: HashMap::new(),
+        }
+    }
+
+    pub fn established(&mut self, peer: PeerId, endpoint: ConnectedPoint, now: Instant) {
+        self.connections
+            .entry(peer)
+            .or_default()
+            .push((endpoint, now));
+    }
+
+    /// Forgets the connection of `peer` over `endpoint`. The swarm does not
+    /// report connection ids, so the oldest connection with the same endpoint
+    /// is forgotten.
+    pub fn closed(&mut self, peer: &PeerId, endpoint: &ConnectedPoint) {
+        let connections = match self.connections.get_mut(peer) {
+            Some(connections) => connections,
+            None => return,
+        };
+
+        if let Some(index) = connections
+            .iter()
+            .position(|(connected, _)| connected == endpoint)
+        {
+            connections.remove(index);
+        }
+        if connections.is_empty() {
+            self.connections.remove(peer);
+        }
+    }
+
+    /// Removes the peers whose oldest connection is older than the maximum age
+    /// at `now`, unless `exempt` returns true for them. Returns the endpoints
+    /// and ages of the connections of the removed peers.
+    pub fn expired(
+        &mut self,
+        now: Instant,
+        exempt: impl Fn(&PeerId) -> bool,
+    ) -> Vec<(PeerId, Vec<(ConnectedPoint, Duration)>)> {
+        let max_age = self.max_age;
+        let expired =
+            self.connections
+                .iter()
+                .filter(|(peer, connections)| {
+                    connections.iter().any(|(_, established)| {
+                        now.saturating_duration_since(*established) > max_age
+                    }) && !exempt(peer)
+                })
+                .map(|(peer, _)| *peer)
+                .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|peer| {
+                let connections = self.connections.remove(&peer)?;
+                let ages = connections
+                    .into_iter()
+                    .map(|(endpoint, established)| {
+                        (endpoint, now.saturating_duration_since(established))
+                    })
+                    .collect();
+
+                Some((peer, ages))
+            })
+            .collect()
+    }
+}
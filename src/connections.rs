@@ -0,0 +1,60 @@
+use crate::registrations::unix_timestamp;
+use libp2p::core::ConnectedPoint;
+use libp2p::PeerId;
+use std::collections::HashMap;
+
+/// Open connections of the connected peers, tracked from the connection
+/// events of the swarm for the admin API and to look up the remote IPs of
+/// peers.
+#[derive(Debug, Default)]
+pub struct Connections {
+    peers: HashMap<PeerId, Vec<Connection>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub endpoint: ConnectedPoint,
+    /// Seconds since the unix epoch at which the connection was established
+    pub established: u64,
+}
+
+impl Connections {
+    pub fn established(&mut self, peer: PeerId, endpoint: ConnectedPoint) {
+        self.peers.entry(peer).or_default().push(Connection {
+            endpoint,
+            established: unix_timestamp(),
+        });
+    }
+
+    /// Forgets the oldest connection of `peer` over `endpoint`, the swarm does
+    /// not report connection ids.
+    pub fn closed(&mut self, peer: &PeerId, endpoint: &ConnectedPoint) {
+        let connections = match self.peers.get_mut(peer) {
+            Some(connections) => connections,
+            None => return,
+        };
+
+        if let Some(index) = connections
+            .iter()
+            .position(|connection| connection.endpoint == *endpoint)
+        {
+            connections.remove(index);
+        }
+        if connections.is_empty() {
+            self.peers.remove(peer);
+        }
+    }
+
+    /// Returns the open connections of `peer`, oldest first.
+    pub fn get(&self, peer: &PeerId) -> &[Connection] {
+        self.peers
+            .get(peer)
+            .map_or(&[][..], |connections| connections.as_slice())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PeerId, &[Connection])> {
+        self.peers
+            .iter()
+            .map(|(peer, connections)| (peer, connections.as_slice()))
+    }
+}
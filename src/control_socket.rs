@@ -0,0 +1,151 @@
+use crate::registrations::{unix_timestamp, Registration, Registrations};
+use anyhow::{bail, Context, Result};
+use std::fs::Permissions;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Command requesting the active registrations as JSON array
+const REGISTRATIONS_COMMAND: &str = "registrations";
+
+/// Answers commands of other instances of the binary on a Unix domain socket.
+///
+/// Clients send a single command line and receive a single JSON line before
+/// the connection is closed. The only command is `registrations`, answered
+/// with the active registrations.
+///
+/// The socket is only accessible by the owner of the process.
+pub fn bind(path: &Path, registrations: Arc<RwLock<Registrations>>) -> Result<()> {
+    remove_stale_socket(path, "control socket")?;
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+    std::fs::set_permissions(path, Permissions::from_mode(0o600)).with_context(|| {
+        format!(
+            "Failed to restrict permissions of control socket {}",
+            path.display()
+        )
+    })?;
+
+    tracing::info!(path = %path.display(), "Accepting commands on control socket");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(stream, registrations.clone()));
+                }
+                Err(error) => {
+                    tracing::warn!(%error, "Failed to accept control socket client");
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Removes the `name` socket a previous instance left behind at `path`.
+/// Fails if `path` is not a socket or another instance still accepts
+/// connections on it.
+pub(crate) fn remove_stale_socket(path: &Path, name: &str) -> Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to inspect {} {}", name, path.display()))
+        }
+    };
+
+    if !metadata.file_type().is_socket() {
+        bail!(
+            "{} exists and is not a socket, refusing to replace it with the {}",
+            path.display(),
+            name
+        );
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("{} {} is in use by another instance", name, path.display());
+    }
+
+    std::fs::remove_file(path)
+        .with_context(|| format!("Failed to remove stale {} {}", name, path.display()))
+}
+
+async fn serve_client(stream: UnixStream, registrations: Arc<RwLock<Registrations>>) {
+    let (reader, mut writer) = stream.into_split();
+    let command = match BufReader::new(reader).lines().next_line().await {
+        Ok(Some(command)) => command,
+        Ok(None) => return,
+        Err(error) => {
+            tracing::debug!(%error, "Failed to read control socket command");
+            return;
+        }
+    };
+
+    let reply = match command.trim() {
+        REGISTRATIONS_COMMAND => {
+            let registrations = registrations
+                .read()
+                .expect("registrations are not updated after a panic");
+            let active = registrations
+                .active(unix_timestamp())
+                .collect::<Vec<&Registration>>();
+
+            serde_json::to_string(&active)
+        }
+        command => serde_json::to_string(&serde_json::json!({
+            "error": format!("Unknown command {}", command)
+        })),
+    };
+    let mut reply = match reply {
+        Ok(reply) => reply,
+        Err(error) => {
+            tracing::warn!(%error, "Failed to serialize control socket reply");
+            return;
+        }
+    };
+    reply.push('\n');
+
+    if let Err(error) = writer.write_all(reply.as_bytes()).await {
+        tracing::debug!(%error, "Failed to answer control socket command");
+    }
+}
+
+/// Requests the active registrations from the server listening on the control
+/// socket at `path` and returns them as JSON array. Fails if the server
+/// answers with an error.
+pub async fn request_registrations(path: &Path) -> Result<String> {
+    let stream = UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Failed to connect to control socket {}", path.display()))?;
+    let (reader, mut writer) = stream.into_split();
+
+    writer
+        .write_all(format!("{}\n", REGISTRATIONS_COMMAND).as_bytes())
+        .await
+        .context("Failed to send command to control socket")?;
+
+    let reply = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await
+        .context("Failed to read reply from control socket")?;
+    let reply = match reply {
+        Some(reply) => reply,
+        None => bail!("Control socket closed the connection without reply"),
+    };
+
+    if let Ok(serde_json::Value::Object(object)) = serde_json::from_str(&reply) {
+        if let Some(error) = object.get("error") {
+            bail!(
+                "Control socket answered with an error: {}",
+                error.as_str().unwrap_or_default()
+            );
+        }
+    }
+
+    Ok(reply)
+}
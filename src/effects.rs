@@ -0,0 +1,489 @@
+use crate::audit::{self, AuditLog};
+use crate::event::{self, ServerEvent};
+#[cfg(unix)]
+use crate::event_socket::{self, EventSocket};
+use crate::metrics::Metrics;
+use crate::registration_log::RegistrationLog;
+use crate::registrations::Registrations;
+use crate::summary::Namespaces;
+use crate::throttle::RefreshLogThrottle;
+use crate::Addresses;
+use libp2p::rendezvous::Registration;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+#[cfg(feature = "grpc")]
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+
+/// Side effect of a swarm event that does not need access to the swarm.
+#[derive(Debug)]
+pub enum Effect {
+    Registered(Registration),
+    Expired(Registration),
+    Unregistered {
+        peer: PeerId,
+        namespace: String,
+    },
+    DiscoverServed {
+        enquirer: PeerId,
+        registrations: Vec<Registration>,
+    },
+    /// Registration removed through the admin API or to make room for a new
+    /// one
+    Evicted {
+        peer: PeerId,
+        namespace: String,
+        /// Whether the registration was reported by the rendezvous behaviour
+        /// and thus counted as active
+        reported: bool,
+    },
+}
+
+/// Maximum number of effects queued while the task is behind. Once reached,
+/// the oldest queued effects are dropped.
+const MAX_BACKLOG: usize = 100_000;
+
+#[derive(Debug)]
+struct Queued {
+    effect: Effect,
+    /// Whether the event socket and the events sender are notified
+    notify: bool,
+}
+
+/// Hands effects from the event loop to the task handling them without ever
+/// waiting for the task.
+///
+/// If the channel to the task is full, effects are queued in order until the
+/// task catches up. Queued effects only update the logs, metrics, tracked
+/// registrations and the mirror, their notifications to the event socket, the
+/// events sender and the gRPC event subscribers are dropped. Served discover
+/// requests are dropped entirely instead of being queued, they carry all
+/// returned registrations and only update the logs and metrics.
+///
+/// At most [`MAX_BACKLOG`] effects are queued, the oldest effects are dropped
+/// beyond that and the tracked registrations may diverge until the peers
+/// refresh their registrations.
+pub struct Effects {
+    sender: mpsc::Sender<Queued>,
+    buffer: usize,
+    backlog: VecDeque<Queued>,
+    /// Number of notifications dropped since the task fell behind
+    dropped: usize,
+    /// Number of served discover requests dropped since the task fell behind
+    dropped_discovers: usize,
+    /// Number of queued effects dropped since the backlog was full
+    dropped_effects: usize,
+}
+
+impl Effects {
+    pub fn submit(&mut self, effect: Effect) {
+        // queue behind the backlog to keep effects in order
+        if !self.backlog.is_empty() {
+            self.queue(effect);
+            return;
+        }
+
+        match self.sender.try_send(Queued {
+            effect,
+            notify: true,
+        }) {
+            Ok(()) => {}
+            Err(TrySendError::Full(Queued { effect, .. })) => {
+                self.queue(effect);
+                if self.is_lagging() {
+                    tracing::warn!(
+                        buffer = self.buffer,
+                        "Event handling is falling behind, dropping event notifications"
+                    );
+                }
+            }
+            Err(TrySendError::Closed(_)) => {
+                tracing::debug!("Event handler stopped, dropping event");
+            }
+        }
+    }
+
+    /// Whether effects are waiting for room in the channel.
+    pub fn is_lagging(&self) -> bool {
+        !self.backlog.is_empty()
+    }
+
+    /// Waits for room in the channel and moves the oldest queued effect into
+    /// it.
+    ///
+    /// Cancelling the future does not lose any effects.
+    pub async fn forward_backlog(&mut self) {
+        let permit = match self.sender.reserve().await {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.backlog.clear();
+                return;
+            }
+        };
+
+        if let Some(queued) = self.backlog.pop_front() {
+            permit.send(queued);
+        }
+
+        if self.backlog.is_empty() {
+            tracing::info!(
+                dropped = self.dropped,
+                dropped_discovers = self.dropped_discovers,
+                dropped_effects = self.dropped_effects,
+                "Event handling caught up, event notifications are delivered again"
+            );
+            self.dropped = 0;
+            self.dropped_discovers = 0;
+            self.dropped_effects = 0;
+        }
+    }
+
+    /// Hands the queued effects to the task and waits until it handled all
+    /// effects.
+    pub async fn close(mut self, handler: JoinHandle<()>) {
+        while let Some(queued) = self.backlog.pop_front() {
+            if self.sender.send(queued).await.is_err() {
+                break;
+            }
+        }
+        drop(self.sender);
+
+        if let Err(error) = handler.await {
+            tracing::error!(%error, "Event handler failed");
+        }
+    }
+
+    fn queue(&mut self, effect: Effect) {
+        if let Effect::DiscoverServed { .. } = effect {
+            self.dropped_discovers += 1;
+            return;
+        }
+
+        if self.backlog.len() >= MAX_BACKLOG {
+            if self.dropped_effects == 0 {
+                tracing::warn!(
+                    max_backlog = MAX_BACKLOG,
+                    "Event handling backlog is full, dropping the oldest events"
+                );
+            }
+            self.backlog.pop_front();
+            self.dropped_effects += 1;
+        }
+
+        self.dropped += 1;
+        self.backlog.push_back(Queued {
+            effect,
+            notify: false,
+        });
+    }
+}
+
+/// Handles the effects of swarm events in a separate task and logs the
+/// summary of the active registrations.
+pub struct Handler {
+    pub metrics: Arc<Metrics>,
+    pub registrations: Arc<RwLock<Registrations>>,
+    pub audit_log: Arc<RwLock<AuditLog>>,
+    pub summary_interval: Option<Duration>,
+    /// Don't log served discover requests
+    pub quiet: bool,
+    /// Suppresses the logs of refreshed registrations
+    pub refresh_log_throttle: Option<RefreshLogThrottle>,
+    #[cfg(unix)]
+    pub event_socket: Option<EventSocket>,
+    pub registration_log: Option<RegistrationLog>,
+    pub events: Option<mpsc::Sender<ServerEvent>>,
+    /// Sender of the events streamed to a standby server
+    pub mirror: Option<mpsc::Sender<ServerEvent>>,
+    /// Subscribers of the gRPC `WatchEvents` stream
+    #[cfg(feature = "grpc")]
+    pub grpc_events: Option<broadcast::Sender<ServerEvent>>,
+}
+
+impl Handler {
+    /// Spawns the task handling the effects submitted through the returned
+    /// [`Effects`]. At most `buffer` effects are buffered before effects are
+    /// queued by [`Effects`].
+    pub fn spawn(self, buffer: usize) -> (Effects, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(buffer);
+        let handler = tokio::spawn(self.run(receiver));
+
+        let effects = Effects {
+            sender,
+            buffer,
+            backlog: VecDeque::new(),
+            dropped: 0,
+            dropped_discovers: 0,
+            dropped_effects: 0,
+        };
+
+        (effects, handler)
+    }
+
+    async fn run(mut self, mut receiver: mpsc::Receiver<Queued>) {
+        let mut namespaces = Namespaces::default();
+
+        // `interval` panics on a zero period, the branch is disabled in that case
+        let summary_enabled = self
+            .summary_interval
+            .map_or(false, |period| period > Duration::from_secs(0));
+        let mut summary_interval = tokio::time::interval(
+            self.summary_interval
+                .unwrap_or_default()
+                .max(Duration::from_secs(1)),
+        );
+        let mut suppressed_interval = tokio::time::interval(
+            self.refresh_log_throttle
+                .as_ref()
+                .map_or(Duration::from_secs(1), RefreshLogThrottle::window),
+        );
+
+        loop {
+            tokio::select! {
+                queued = receiver.recv() => {
+                    match queued {
+                        Some(Queued { effect, notify }) => self.handle(&mut namespaces, effect, notify),
+                        None => break,
+                    }
+                }
+                _ = summary_interval.tick(), if summary_enabled => {
+                    tracing::info!(registrations=%namespaces, "Active registrations per namespace");
+                }
+                _ = suppressed_interval.tick(), if self.refresh_log_throttle.is_some() => {
+                    if let Some(throttle) = &mut self.refresh_log_throttle {
+                        let suppressed = throttle.take_suppressed();
+                        if suppressed > 0 {
+                            tracing::info!(suppressed, window_secs=throttle.window().as_secs(), "Registration refreshes suppressed");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, namespaces: &mut Namespaces, effect: Effect, notify: bool) {
+        match effect {
+            Effect::Registered(registration) => {
+                let peer = registration.record.peer_id();
+                let namespace = registration.namespace.to_string();
+
+                let log = self.refresh_log_throttle.as_mut().map_or(true, |throttle| {
+                    throttle.should_log(peer, &namespace, Instant::now())
+                });
+                if log {
+                    tracing::info!(%peer, %namespace, addresses=?registration.record.addresses(), ttl=registration.ttl, "Peer registered");
+                }
+                let refresh = !namespaces.add(namespace.clone(), peer);
+                self.metrics.peer_registered(&namespace, refresh);
+                self.metrics.granted_ttl(registration.ttl);
+                if notify {
+                    #[cfg(unix)]
+                    if let Some(event_socket) = &self.event_socket {
+                        event_socket.publish(event_socket::Event::registered(&registration));
+                    }
+                }
+                self.forward(ServerEvent::registered(&registration), notify);
+                if let Some(registration_log) = &mut self.registration_log {
+                    registration_log.registered(&registration);
+                }
+                self.audit(
+                    audit::Kind::Registered,
+                    peer,
+                    Some(namespace.clone()),
+                    registration.record.addresses().to_vec(),
+                );
+                self.registrations
+                    .write()
+                    .expect("registrations are not updated after a panic")
+                    .insert(
+                        peer,
+                        namespace,
+                        registration.record.addresses().to_vec(),
+                        registration.ttl,
+                        Some(
+                            registration
+                                .record
+                                .to_signed_envelope()
+                                .into_protobuf_encoding(),
+                        ),
+                        false,
+                    );
+            }
+            Effect::Expired(registration) => {
+                let peer = registration.record.peer_id();
+                let namespace = registration.namespace.to_string();
+
+                tracing::info!(%peer, %namespace, addresses=%Addresses(registration.record.addresses()), ttl=registration.ttl, "Registration expired");
+                self.forget_logged(peer, &namespace);
+                self.metrics.registration_expired(&namespace);
+                if notify {
+                    #[cfg(unix)]
+                    if let Some(event_socket) = &self.event_socket {
+                        event_socket.publish(event_socket::Event::expired(&registration));
+                    }
+                }
+                self.forward(ServerEvent::expired(&registration), notify);
+                if let Some(registration_log) = &mut self.registration_log {
+                    registration_log.expired(&registration);
+                }
+                self.audit(
+                    audit::Kind::Expired,
+                    peer,
+                    Some(namespace.clone()),
+                    registration.record.addresses().to_vec(),
+                );
+                namespaces.remove(&namespace, &peer);
+                self.registrations
+                    .write()
+                    .expect("registrations are not updated after a panic")
+                    .remove(&peer, &namespace);
+            }
+            Effect::Unregistered { peer, namespace } => {
+                tracing::info!(%peer, %namespace, "Peer unregistered");
+                self.forget_logged(peer, &namespace);
+                self.metrics.peer_unregistered(&namespace);
+                if notify {
+                    #[cfg(unix)]
+                    if let Some(event_socket) = &self.event_socket {
+                        event_socket
+                            .publish(event_socket::Event::unregistered(peer, namespace.clone()));
+                    }
+                }
+                self.forward(
+                    ServerEvent::PeerUnregistered {
+                        peer,
+                        namespace: namespace.clone(),
+                    },
+                    notify,
+                );
+                if let Some(registration_log) = &mut self.registration_log {
+                    registration_log.unregistered(peer, &namespace);
+                }
+                self.audit(
+                    audit::Kind::Unregistered,
+                    peer,
+                    Some(namespace.clone()),
+                    Vec::new(),
+                );
+                namespaces.remove(&namespace, &peer);
+                self.registrations
+                    .write()
+                    .expect("registrations are not updated after a panic")
+                    .remove(&peer, &namespace);
+            }
+            Effect::DiscoverServed {
+                enquirer,
+                registrations,
+            } => {
+                // the event does not tell which namespace was requested, only
+                // the namespaces of the returned registrations are known
+                let served = registrations
+                    .iter()
+                    .map(|registration| registration.namespace.to_string())
+                    .collect::<BTreeSet<_>>();
+                if !self.quiet {
+                    tracing::info!(peer=%enquirer, registrations=registrations.len(), namespaces=?served, "Discovery served");
+                }
+                self.metrics.discover_served();
+                if registrations.is_empty() {
+                    // often a client querying a misspelled namespace or one
+                    // of another version
+                    tracing::debug!(peer=%enquirer, "Discovery returned no registrations");
+                    self.metrics.empty_discover();
+                }
+                if notify {
+                    #[cfg(unix)]
+                    if let Some(event_socket) = &self.event_socket {
+                        event_socket.publish(event_socket::Event::discover_served(enquirer));
+                    }
+                }
+                self.forward(ServerEvent::DiscoverServed { enquirer }, notify);
+                self.audit(audit::Kind::DiscoverServed, enquirer, None, Vec::new());
+            }
+            Effect::Evicted {
+                peer,
+                namespace,
+                reported,
+            } => {
+                self.forget_logged(peer, &namespace);
+                if reported {
+                    self.metrics.registration_evicted(&namespace);
+                }
+                self.audit(
+                    audit::Kind::Evicted,
+                    peer,
+                    Some(namespace.clone()),
+                    Vec::new(),
+                );
+                namespaces.remove(&namespace, &peer);
+                // the standby cannot tell evictions apart from unregistrations
+                self.mirror(ServerEvent::PeerUnregistered {
+                    peer,
+                    namespace: namespace.clone(),
+                });
+                // already removed if evicted through the admin API
+                self.registrations
+                    .write()
+                    .expect("registrations are not updated after a panic")
+                    .remove(&peer, &namespace);
+            }
+        }
+    }
+
+    /// Forwards `event` to the mirror and, if `notify` is set, to the events
+    /// sender and the gRPC event subscribers. The mirror receives events even
+    /// while notifications are dropped, otherwise the standby would diverge.
+    fn forward(&self, event: ServerEvent, notify: bool) {
+        self.mirror(event.clone());
+        if !notify {
+            return;
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(grpc_events) = &self.grpc_events {
+            // fails only if no subscriber is connected
+            let _ = grpc_events.send(event.clone());
+        }
+        event::forward(&self.events, event);
+    }
+
+    fn mirror(&self, event: ServerEvent) {
+        let mirror = match &self.mirror {
+            Some(mirror) => mirror,
+            None => return,
+        };
+
+        match mirror.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => {
+                tracing::warn!(?event, "Mirror is lagging behind, dropped event, the standby is stale until the registration is refreshed or expires");
+            }
+            Err(TrySendError::Closed(_)) => {
+                tracing::debug!("Mirror was stopped, not forwarding event");
+            }
+        }
+    }
+
+    fn forget_logged(&mut self, peer: PeerId, namespace: &str) {
+        if let Some(throttle) = &mut self.refresh_log_throttle {
+            throttle.remove(peer, namespace);
+        }
+    }
+
+    fn audit(
+        &self,
+        kind: audit::Kind,
+        peer: PeerId,
+        namespace: Option<String>,
+        addresses: Vec<Multiaddr>,
+    ) {
+        self.audit_log
+            .write()
+            .expect("audit log is not updated after a panic")
+            .record(kind, peer, namespace, addresses);
+    }
+}
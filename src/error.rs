@@ -0,0 +1,40 @@
+use libp2p::core::transport::TransportError;
+use libp2p::Multiaddr;
+use std::error::Error;
+use std::io;
+
+type Source = Box<dyn Error + Send + Sync>;
+
+/// Errors returned by [`RendezvousServer`](crate::RendezvousServer) and the
+/// transport and swarm constructors.
+///
+/// The underlying error is kept as the source, so converting into
+/// `anyhow::Error` keeps the whole chain.
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    /// The configuration is invalid or inconsistent
+    #[error("{0}")]
+    Config(String),
+    #[error("Failed to load the TLS configuration")]
+    TlsConfig(#[source] Source),
+    #[error("Failed to load the allow peer file")]
+    AllowPeerFile(#[source] Source),
+    #[error("Failed to create transport")]
+    TransportBuild(#[source] Source),
+    #[error("Failed to initialize listener on {address}")]
+    Listen {
+        address: Multiaddr,
+        #[source]
+        source: TransportError<io::Error>,
+    },
+    /// Loading or saving the registrations failed
+    #[error("Failed to access the state file")]
+    StateFile(#[source] Source),
+    /// One of the HTTP servers, the event socket or the signal handlers could
+    /// not be set up
+    #[error("Failed to start the server")]
+    Startup(#[source] Source),
+    /// The server stopped because it cannot serve clients anymore
+    #[error("The server stopped unexpectedly")]
+    Stopped(#[source] Source),
+}
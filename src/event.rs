@@ -0,0 +1,104 @@
+use libp2p::rendezvous::Registration;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// Rendezvous event forwarded to an embedding application through the sender
+/// configured in [`ServerConfig::events`](crate::ServerConfig::events).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    PeerRegistered {
+        #[serde(
+            serialize_with = "serialize_peer_id",
+            deserialize_with = "deserialize_peer_id"
+        )]
+        peer: PeerId,
+        namespace: String,
+        addresses: Vec<Multiaddr>,
+        ttl: u64,
+    },
+    PeerUnregistered {
+        #[serde(
+            serialize_with = "serialize_peer_id",
+            deserialize_with = "deserialize_peer_id"
+        )]
+        peer: PeerId,
+        namespace: String,
+    },
+    RegistrationExpired {
+        #[serde(
+            serialize_with = "serialize_peer_id",
+            deserialize_with = "deserialize_peer_id"
+        )]
+        peer: PeerId,
+        namespace: String,
+        addresses: Vec<Multiaddr>,
+        ttl: u64,
+    },
+    DiscoverServed {
+        #[serde(
+            serialize_with = "serialize_peer_id",
+            deserialize_with = "deserialize_peer_id"
+        )]
+        enquirer: PeerId,
+    },
+}
+
+impl ServerEvent {
+    pub(crate) fn registered(registration: &Registration) -> Self {
+        ServerEvent::PeerRegistered {
+            peer: registration.record.peer_id(),
+            namespace: registration.namespace.to_string(),
+            addresses: registration.record.addresses().to_vec(),
+            ttl: registration.ttl,
+        }
+    }
+
+    pub(crate) fn expired(registration: &Registration) -> Self {
+        ServerEvent::RegistrationExpired {
+            peer: registration.record.peer_id(),
+            namespace: registration.namespace.to_string(),
+            addresses: registration.record.addresses().to_vec(),
+            ttl: registration.ttl,
+        }
+    }
+}
+
+/// Forwards `event` to `sender` if one is configured.
+///
+/// Events are dropped instead of stalling the swarm if the receiver does not
+/// keep up.
+pub(crate) fn forward(sender: &Option<mpsc::Sender<ServerEvent>>, event: ServerEvent) {
+    let sender = match sender {
+        Some(sender) => sender,
+        None => return,
+    };
+
+    match sender.try_send(event) {
+        Ok(()) => {}
+        Err(TrySendError::Full(event)) => {
+            tracing::warn!(?event, "Event receiver is lagging behind, dropped event");
+        }
+        Err(TrySendError::Closed(_)) => {
+            tracing::debug!("Event receiver was dropped, not forwarding event");
+        }
+    }
+}
+
+fn serialize_peer_id<S>(peer: &PeerId, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(peer)
+}
+
+fn deserialize_peer_id<'de, D>(deserializer: D) -> Result<PeerId, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let peer = String::deserialize(deserializer)?;
+
+    peer.parse().map_err(serde::de::Error::custom)
+}
@@ -0,0 +1,158 @@
+use crate::control_socket::remove_stale_socket;
+use crate::registrations::unix_timestamp;
+use anyhow::{Context, Result};
+use libp2p::rendezvous::Registration;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Number of events buffered per connected client before the oldest ones are
+/// dropped
+const CLIENT_BUFFER: usize = 1024;
+
+/// Streams rendezvous events as JSON lines to all clients connected to a Unix
+/// domain socket.
+///
+/// Events are dropped if no client is connected.
+pub struct EventSocket {
+    sender: broadcast::Sender<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    Registered,
+    Unregistered,
+    Expired,
+    DiscoverServed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Event {
+    #[serde(rename = "type")]
+    kind: Kind,
+    peer_id: String,
+    namespace: Option<String>,
+    addresses: Vec<Multiaddr>,
+    ttl: Option<u64>,
+    /// Seconds since the unix epoch at which the event was handled
+    timestamp: u64,
+}
+
+impl Event {
+    pub fn registered(registration: &Registration) -> Self {
+        Self::from_registration(Kind::Registered, registration)
+    }
+
+    pub fn expired(registration: &Registration) -> Self {
+        Self::from_registration(Kind::Expired, registration)
+    }
+
+    pub fn unregistered(peer: PeerId, namespace: String) -> Self {
+        Self::new(Kind::Unregistered, peer, Some(namespace))
+    }
+
+    pub fn discover_served(enquirer: PeerId) -> Self {
+        Self::new(Kind::DiscoverServed, enquirer, None)
+    }
+
+    fn from_registration(kind: Kind, registration: &Registration) -> Self {
+        Self {
+            addresses: registration.record.addresses().to_vec(),
+            ttl: Some(registration.ttl),
+            ..Self::new(
+                kind,
+                registration.record.peer_id(),
+                Some(registration.namespace.to_string()),
+            )
+        }
+    }
+
+    fn new(kind: Kind, peer: PeerId, namespace: Option<String>) -> Self {
+        Self {
+            kind,
+            peer_id: peer.to_string(),
+            namespace,
+            addresses: Vec::new(),
+            ttl: None,
+            timestamp: unix_timestamp(),
+        }
+    }
+}
+
+impl EventSocket {
+    /// Binds a Unix domain socket at `path` and accepts clients in a separate
+    /// task. A stale socket file at `path` is removed. The socket is only
+    /// accessible by the owner of the process.
+    pub fn bind(path: &Path) -> Result<Self> {
+        remove_stale_socket(path, "event socket")?;
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind event socket {}", path.display()))?;
+        std::fs::set_permissions(path, Permissions::from_mode(0o600)).with_context(|| {
+            format!(
+                "Failed to restrict permissions of event socket {}",
+                path.display()
+            )
+        })?;
+        let (sender, _) = broadcast::channel(CLIENT_BUFFER);
+
+        tracing::info!(path = %path.display(), "Streaming events on unix socket");
+
+        let accept_sender = sender.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_client(stream, accept_sender.subscribe()));
+                    }
+                    Err(error) => {
+                        tracing::warn!(%error, "Failed to accept event socket client");
+                    }
+                }
+            }
+        });
+
+        Ok(Self { sender })
+    }
+
+    pub fn publish(&self, event: Event) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+
+        match serde_json::to_string(&event) {
+            Ok(mut line) => {
+                line.push('\n');
+                let _ = self.sender.send(line);
+            }
+            Err(error) => tracing::warn!(%error, "Failed to serialize event"),
+        }
+    }
+}
+
+async fn serve_client(mut stream: UnixStream, mut events: broadcast::Receiver<String>) {
+    loop {
+        let line = match events.recv().await {
+            Ok(line) => line,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::debug!(
+                    skipped,
+                    "Event socket client is lagging behind, dropped events"
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        if stream.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
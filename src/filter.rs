@@ -0,0 +1,739 @@
+use libp2p::core::connection::{ConnectionId, ListenerId};
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::rendezvous::{Event, Namespace, Rendezvous};
+use libp2p::swarm::{
+    CloseConnection, IntoProtocolsHandler, NetworkBehaviour, NetworkBehaviourAction,
+    PollParameters, ProtocolsHandler,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+
+type Handler = <Rendezvous as NetworkBehaviour>::ProtocolsHandler;
+type HandlerInEvent = <<Handler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent;
+type HandlerOutEvent = <<Handler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent;
+
+/// Default maximum number of addresses in a registration.
+pub const DEFAULT_MAX_ADDRESSES_PER_REGISTRATION: usize = 16;
+
+/// Restrictions on which registrations the rendezvous server accepts.
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// Namespaces peers are allowed to register in. All namespaces are allowed
+    /// if empty.
+    pub allowed_namespaces: HashSet<String>,
+    /// Peers that are allowed to register. All peers that are not denied are
+    /// allowed if empty. Takes precedence over `denied_peers`.
+    pub allowed_peers: HashSet<PeerId>,
+    /// Peers that are allowed to register, loaded from a file that can change
+    /// at runtime. If set, only peers in it or in `allowed_peers` can
+    /// register, even if it is empty.
+    pub allowed_peers_file: Option<Arc<RwLock<HashSet<PeerId>>>>,
+    /// Peers that are not allowed to register.
+    pub denied_peers: HashSet<PeerId>,
+    /// Maximum number of namespaces a peer can be registered in at the same
+    /// time. Not limited if `None`.
+    pub max_namespaces_per_peer: Option<usize>,
+    /// Keep the connections to a peer whose registration exceeds
+    /// `max_namespaces_per_peer` open instead of closing them.
+    pub keep_connection_over_namespace_cap: bool,
+    /// Maximum number of registrations across all peers. Once reached,
+    /// requests of peers without a registration are dropped before they reach
+    /// the store of the inner behaviour. Not limited if `None`.
+    pub max_total_registrations: Option<usize>,
+    /// Evict the oldest registration instead of rejecting a new one once
+    /// `max_total_registrations` is reached.
+    pub evict_on_full: bool,
+    /// Only accept registrations of peers that completed identify
+    pub require_identify: bool,
+    /// Only accept registrations of peers whose identify agent version starts
+    /// with this prefix. Implies `require_identify`.
+    pub required_agent_prefix: Option<String>,
+    /// Peers that completed identify, filled by the server
+    pub identified_peers: IdentifiedPeers,
+    /// Reject registrations with a private, loopback or otherwise unroutable
+    /// address
+    pub reject_private_addresses: bool,
+    /// Accept registrations with unroutable addresses as long as one routable
+    /// address remains. The addresses are part of the signed peer record and
+    /// cannot be removed, discoverers still receive them.
+    pub allow_partially_routable: bool,
+    /// Maximum number of addresses in a registration. Not limited if `None`.
+    pub max_addresses_per_registration: Option<usize>,
+}
+
+/// Agent versions of the connected peers that completed identify.
+#[derive(Debug, Default, Clone)]
+pub struct IdentifiedPeers(Arc<RwLock<HashMap<PeerId, String>>>);
+
+impl IdentifiedPeers {
+    pub(crate) fn insert(&self, peer: PeerId, agent_version: String) {
+        self.0
+            .write()
+            .expect("identified peers are not updated after a panic")
+            .insert(peer, agent_version);
+    }
+
+    pub(crate) fn remove(&self, peer: &PeerId) {
+        self.0
+            .write()
+            .expect("identified peers are not updated after a panic")
+            .remove(peer);
+    }
+
+    fn agent_version(&self, peer: &PeerId) -> Option<String> {
+        self.0
+            .read()
+            .expect("identified peers are not updated after a panic")
+            .get(peer)
+            .cloned()
+    }
+}
+
+impl Policy {
+    /// Returns the reason for rejecting registrations of `peer`, if any.
+    fn violation(&self, peer: &PeerId) -> Option<&'static str> {
+        if self.allowed_peers.contains(peer) || self.is_in_allowed_peers_file(peer) {
+            return None;
+        }
+        if self.denied_peers.contains(peer) {
+            return Some("peer is denied");
+        }
+        if !self.allowed_peers.is_empty() || self.allowed_peers_file.is_some() {
+            return Some("peer is not allowed");
+        }
+
+        None
+    }
+
+    /// Returns the reason for rejecting registrations in `namespace`, if any.
+    fn namespace_violation(&self, namespace: &str) -> Option<&'static str> {
+        if !self.allowed_namespaces.is_empty() && !self.allowed_namespaces.contains(namespace) {
+            return Some("namespace is not allowed");
+        }
+
+        None
+    }
+
+    /// Returns the reason for rejecting registrations of `peer` based on its
+    /// identify info, if any.
+    ///
+    /// Unlike [`Policy::violation`], the result changes once the peer
+    /// identifies.
+    fn identify_violation(&self, peer: &PeerId) -> Option<&'static str> {
+        if !self.require_identify && self.required_agent_prefix.is_none() {
+            return None;
+        }
+
+        let agent_version = match self.identified_peers.agent_version(peer) {
+            Some(agent_version) => agent_version,
+            None => return Some("peer did not identify"),
+        };
+
+        match &self.required_agent_prefix {
+            Some(prefix) if !agent_version.starts_with(prefix.as_str()) => {
+                Some("agent version does not match")
+            }
+            _ => None,
+        }
+    }
+
+    fn is_in_allowed_peers_file(&self, peer: &PeerId) -> bool {
+        self.allowed_peers_file.as_ref().map_or(false, |peers| {
+            peers
+                .read()
+                .expect("allowed peers are not updated after a panic")
+                .contains(peer)
+        })
+    }
+
+    /// Returns the unroutable addresses of a registration if they are
+    /// checked.
+    fn unroutable_addresses<'a>(&self, addresses: &'a [Multiaddr]) -> Vec<&'a Multiaddr> {
+        if !self.reject_private_addresses && !self.allow_partially_routable {
+            return Vec::new();
+        }
+
+        addresses
+            .iter()
+            .filter(|address| !is_routable(address))
+            .collect()
+    }
+}
+
+/// Whether `address` is reachable from the public internet, judged by its IP
+/// component. Addresses without IP, e.g. DNS addresses, count as routable.
+fn is_routable(address: &Multiaddr) -> bool {
+    address.iter().all(|protocol| match protocol {
+        Protocol::Ip4(ip) => is_routable_ip4(ip),
+        Protocol::Ip6(ip) => {
+            // IPv4-mapped addresses, ::ffff:a.b.c.d
+            if let [0, 0, 0, 0, 0, 0xffff, _, _] = ip.segments() {
+                let [.., a, b, c, d] = ip.octets();
+                return is_routable_ip4(Ipv4Addr::new(a, b, c, d));
+            }
+
+            let first = ip.segments()[0];
+            let unique_local = first & 0xfe00 == 0xfc00;
+            let link_local = first & 0xffc0 == 0xfe80;
+
+            !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+        }
+        _ => true,
+    })
+}
+
+fn is_routable_ip4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    let shared = first == 100 && second & 0xc0 == 64;
+
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || shared)
+}
+
+/// Wraps the [`Rendezvous`] behaviour and enforces a [`Policy`] on the
+/// registrations it accepts.
+///
+/// Requests of peers that are denied or not allowed by the policy are dropped
+/// before they reach the rendezvous behaviour, so their registrations are
+/// never stored, and all connections to the peer are closed. These peers
+/// cannot discover either. Responses of the rendezvous servers the server
+/// registers with are always passed on.
+///
+/// The rendezvous behaviour does not expose the content of its protocol
+/// handler events, so the checks that depend on the registration cannot
+/// decline it before it is stored. Instead, registrations failing them are
+/// hidden from the rest of the server. The registration itself stays
+/// discoverable until its TTL expires, which can be bounded with `--max-ttl`.
+///
+/// The cap on namespaces per peer is enforced based on the registrations that
+/// were reported. Registrations exceeding the cap are hidden and all
+/// connections to the peer are closed, unless
+/// [`Policy::keep_connection_over_namespace_cap`] is set.
+///
+/// Registrations of peers that did not identify as required are hidden and
+/// all connections to the peer are closed as well. Clients have to wait for
+/// identify to complete before registering.
+///
+/// Once [`Policy::max_total_registrations`] registrations are reported,
+/// requests of peers without a reported registration are dropped and all
+/// connections to them are closed, so the store of the inner behaviour stops
+/// growing. New registrations of peers that are already registered are
+/// hidden. If [`Policy::evict_on_full`] is set, requests are served and the
+/// oldest registration is evicted to make room instead. Like registrations
+/// evicted through the admin API, hidden and evicted registrations stay in
+/// the store until their TTL expires.
+///
+/// Registrations with more than [`Policy::max_addresses_per_registration`]
+/// addresses are hidden.
+///
+/// Registrations with unroutable addresses are hidden if
+/// [`Policy::reject_private_addresses`] is set, or if no routable address
+/// remains with [`Policy::allow_partially_routable`].
+///
+/// While draining, registrations in namespaces the peer is not registered in
+/// yet are hidden as well and all connections to the peer are closed, so it
+/// moves on to another server. The inner behaviour still stores these
+/// registrations, they stay discoverable until their TTL expires. Refreshes
+/// of reported registrations are accepted.
+pub struct FilteredRendezvous {
+    inner: Rendezvous,
+    policy: Policy,
+    draining: Arc<AtomicBool>,
+    /// Namespaces of the reported registrations per peer
+    namespaces: HashMap<PeerId, HashSet<String>>,
+    /// Registrations hidden for violating the policy, exceeding the namespace
+    /// cap or the total cap, missing identify info, while draining or after
+    /// being evicted
+    hidden: HashSet<(PeerId, String)>,
+    /// Reported registrations in the order they were first reported
+    reported: BTreeMap<u64, (PeerId, String)>,
+    /// Position of each reported registration in `reported`
+    sequences: HashMap<(PeerId, String), u64>,
+    next_sequence: u64,
+    /// Registrations evicted to make room, not yet taken by the server
+    evicted: Vec<(PeerId, String)>,
+    /// Rendezvous servers the server registers with
+    rendezvous_nodes: HashSet<PeerId>,
+    /// Peers whose requests were dropped, connections to them are closed on
+    /// the next poll
+    pending_closes: VecDeque<PeerId>,
+}
+
+impl FilteredRendezvous {
+    pub fn new(inner: Rendezvous, policy: Policy, draining: Arc<AtomicBool>) -> Self {
+        Self {
+            inner,
+            policy,
+            draining,
+            namespaces: HashMap::new(),
+            hidden: HashSet::new(),
+            reported: BTreeMap::new(),
+            sequences: HashMap::new(),
+            next_sequence: 0,
+            evicted: Vec::new(),
+            rendezvous_nodes: HashSet::new(),
+            pending_closes: VecDeque::new(),
+        }
+    }
+
+    /// Returns the registrations evicted to make room for new ones since the
+    /// last call.
+    pub fn take_evicted(&mut self) -> Vec<(PeerId, String)> {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Registers the server itself in `namespace` with another rendezvous
+    /// server.
+    pub fn register(&mut self, namespace: Namespace, rendezvous_node: PeerId, ttl: Option<u64>) {
+        self.rendezvous_nodes.insert(rendezvous_node);
+        self.inner.register(namespace, rendezvous_node, ttl)
+    }
+
+    fn is_reported(&self, peer: &PeerId, namespace: &str) -> bool {
+        self.namespaces
+            .get(peer)
+            .map_or(false, |namespaces| namespaces.contains(namespace))
+    }
+
+    fn exceeds_namespace_cap(&self, peer: &PeerId, namespace: &str) -> bool {
+        let max = match self.policy.max_namespaces_per_peer {
+            Some(max) => max,
+            None => return false,
+        };
+
+        match self.namespaces.get(peer) {
+            Some(namespaces) => !namespaces.contains(namespace) && namespaces.len() >= max,
+            None => max == 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.policy
+            .max_total_registrations
+            .map_or(false, |max| self.sequences.len() >= max)
+    }
+
+    fn report(&mut self, peer: PeerId, namespace: String) {
+        self.hidden.remove(&(peer, namespace.clone()));
+        self.namespaces
+            .entry(peer)
+            .or_default()
+            .insert(namespace.clone());
+
+        if !self.sequences.contains_key(&(peer, namespace.clone())) {
+            self.reported
+                .insert(self.next_sequence, (peer, namespace.clone()));
+            self.sequences.insert((peer, namespace), self.next_sequence);
+            self.next_sequence += 1;
+        }
+    }
+
+    /// Forgets the registration of `peer` in `namespace`. Returns whether the
+    /// registration was reported before.
+    fn forget(&mut self, peer: PeerId, namespace: String) -> bool {
+        if self.hidden.remove(&(peer, namespace.clone())) {
+            return false;
+        }
+
+        self.remove_reported(peer, namespace);
+
+        true
+    }
+
+    fn remove_reported(&mut self, peer: PeerId, namespace: String) {
+        if let Some(namespaces) = self.namespaces.get_mut(&peer) {
+            namespaces.remove(&namespace);
+
+            if namespaces.is_empty() {
+                self.namespaces.remove(&peer);
+            }
+        }
+
+        if let Some(sequence) = self.sequences.remove(&(peer, namespace)) {
+            self.reported.remove(&sequence);
+        }
+    }
+
+    /// Stops reporting the oldest reported registration. Returns `false` if no
+    /// registration is reported.
+    fn evict_oldest(&mut self) -> bool {
+        let (peer, namespace) = match self.reported.values().next() {
+            Some(oldest) => oldest.clone(),
+            None => return false,
+        };
+
+        tracing::warn!(%peer, %namespace, max_total=?self.policy.max_total_registrations, "Evicted oldest registration, reported registrations are at the cap");
+        self.hide(peer, &namespace);
+        self.evicted.push((peer, namespace));
+
+        true
+    }
+
+    /// Hides a rejected registration of `peer` in `namespace`. A rejected
+    /// refresh replaces the reported registration in the store of the inner
+    /// behaviour, so the reported registration is evicted.
+    fn reject(&mut self, peer: PeerId, namespace: String) {
+        if self.hide(peer, &namespace) {
+            self.evicted.push((peer, namespace));
+        } else {
+            self.hidden.insert((peer, namespace));
+        }
+    }
+
+    /// Stops reporting the registration of `peer` in `namespace`, e.g. after
+    /// it was evicted through the admin API. The registration stays in the
+    /// store of the inner behaviour, its expiry is not reported. Returns
+    /// whether the registration was reported.
+    pub fn hide(&mut self, peer: PeerId, namespace: &str) -> bool {
+        if !self.is_reported(&peer, namespace) {
+            return false;
+        }
+
+        self.remove_reported(peer, namespace.to_owned());
+        self.hidden.insert((peer, namespace.to_owned()));
+
+        true
+    }
+}
+
+impl NetworkBehaviour for FilteredRendezvous {
+    type ProtocolsHandler = Handler;
+    type OutEvent = Event;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.inner.new_handler()
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.inner.addresses_of_peer(peer_id)
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.inner.inject_connected(peer_id)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.inner.inject_disconnected(peer_id)
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.inner
+            .inject_connection_established(peer_id, connection, endpoint)
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        self.inner
+            .inject_connection_closed(peer_id, connection, endpoint)
+    }
+
+    fn inject_address_change(
+        &mut self,
+        peer_id: &PeerId,
+        connection: &ConnectionId,
+        old: &ConnectedPoint,
+        new: &ConnectedPoint,
+    ) {
+        self.inner
+            .inject_address_change(peer_id, connection, old, new)
+    }
+
+    fn inject_event(&mut self, peer_id: PeerId, connection: ConnectionId, event: HandlerOutEvent) {
+        if !self.rendezvous_nodes.contains(&peer_id) {
+            if let Some(reason) = self.policy.violation(&peer_id) {
+                // the inner behaviour stores registrations when it receives
+                // the event, dropping it declines the registration
+                if !self.pending_closes.contains(&peer_id) {
+                    tracing::info!(peer=%peer_id, %reason, "Rejected request");
+                    self.pending_closes.push_back(peer_id);
+                }
+
+                return;
+            }
+            if self.is_full()
+                && !self.policy.evict_on_full
+                && !self.namespaces.contains_key(&peer_id)
+            {
+                if !self.pending_closes.contains(&peer_id) {
+                    tracing::warn!(peer=%peer_id, max_total=?self.policy.max_total_registrations, "Rejected request, registrations are at the cap");
+                    self.pending_closes.push_back(peer_id);
+                }
+
+                return;
+            }
+        }
+
+        self.inner.inject_event(peer_id, connection, event)
+    }
+
+    fn inject_addr_reach_failure(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        address: &Multiaddr,
+        error: &dyn Error,
+    ) {
+        self.inner
+            .inject_addr_reach_failure(peer_id, address, error)
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        self.inner.inject_dial_failure(peer_id)
+    }
+
+    fn inject_new_listener(&mut self, id: ListenerId) {
+        self.inner.inject_new_listener(id)
+    }
+
+    fn inject_new_listen_addr(&mut self, id: ListenerId, address: &Multiaddr) {
+        self.inner.inject_new_listen_addr(id, address)
+    }
+
+    fn inject_expired_listen_addr(&mut self, id: ListenerId, address: &Multiaddr) {
+        self.inner.inject_expired_listen_addr(id, address)
+    }
+
+    fn inject_listener_error(&mut self, id: ListenerId, error: &(dyn Error + 'static)) {
+        self.inner.inject_listener_error(id, error)
+    }
+
+    fn inject_listener_closed(&mut self, id: ListenerId, reason: Result<(), &std::io::Error>) {
+        self.inner.inject_listener_closed(id, reason)
+    }
+
+    fn inject_new_external_addr(&mut self, address: &Multiaddr) {
+        self.inner.inject_new_external_addr(address)
+    }
+
+    fn inject_expired_external_addr(&mut self, address: &Multiaddr) {
+        self.inner.inject_expired_external_addr(address)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<HandlerInEvent, Self::OutEvent>> {
+        if let Some(peer_id) = self.pending_closes.pop_front() {
+            return Poll::Ready(NetworkBehaviourAction::CloseConnection {
+                peer_id,
+                connection: CloseConnection::All,
+            });
+        }
+
+        loop {
+            let event = match self.inner.poll(cx, params) {
+                Poll::Ready(NetworkBehaviourAction::GenerateEvent(event)) => event,
+                other => return other,
+            };
+
+            match event {
+                Event::PeerRegistered { peer, registration } => {
+                    // only if the policy changed after the registration was
+                    // stored, e.g. because the allowed peers file was reloaded
+                    if let Some(reason) = self.policy.violation(&peer) {
+                        tracing::info!(%peer, namespace=%registration.namespace, %reason, "Rejected registration");
+                        self.reject(peer, registration.namespace.to_string());
+
+                        return Poll::Ready(NetworkBehaviourAction::CloseConnection {
+                            peer_id: peer,
+                            connection: CloseConnection::All,
+                        });
+                    }
+
+                    let namespace = registration.namespace.to_string();
+                    if let Some(reason) = self.policy.namespace_violation(&namespace) {
+                        tracing::info!(%peer, %namespace, %reason, "Rejected registration");
+                        self.reject(peer, namespace);
+
+                        return Poll::Ready(NetworkBehaviourAction::CloseConnection {
+                            peer_id: peer,
+                            connection: CloseConnection::All,
+                        });
+                    }
+                    if let Some(reason) = self.policy.identify_violation(&peer) {
+                        tracing::info!(%peer, %namespace, %reason, "Rejected registration");
+                        self.reject(peer, namespace);
+
+                        return Poll::Ready(NetworkBehaviourAction::CloseConnection {
+                            peer_id: peer,
+                            connection: CloseConnection::All,
+                        });
+                    }
+                    if self.draining.load(Ordering::SeqCst) && !self.is_reported(&peer, &namespace)
+                    {
+                        tracing::info!(%peer, %namespace, "Rejected registration, server is draining");
+                        self.reject(peer, namespace);
+
+                        return Poll::Ready(NetworkBehaviourAction::CloseConnection {
+                            peer_id: peer,
+                            connection: CloseConnection::All,
+                        });
+                    }
+                    if self.exceeds_namespace_cap(&peer, &namespace) {
+                        tracing::info!(%peer, %namespace, max_namespaces=?self.policy.max_namespaces_per_peer, "Rejected registration, peer exceeds the namespace cap");
+                        self.reject(peer, namespace);
+
+                        if !self.policy.keep_connection_over_namespace_cap {
+                            return Poll::Ready(NetworkBehaviourAction::CloseConnection {
+                                peer_id: peer,
+                                connection: CloseConnection::All,
+                            });
+                        }
+
+                        continue;
+                    }
+
+                    let addresses = registration.record.addresses();
+                    if let Some(max) = self.policy.max_addresses_per_registration {
+                        if addresses.len() > max {
+                            tracing::info!(%peer, %namespace, addresses=addresses.len(), max, "Rejected registration, too many addresses");
+                            self.reject(peer, namespace);
+
+                            continue;
+                        }
+                    }
+                    let unroutable = self.policy.unroutable_addresses(addresses);
+                    if !unroutable.is_empty() {
+                        let reject = self.policy.reject_private_addresses
+                            || unroutable.len() == addresses.len();
+                        for address in &unroutable {
+                            if reject {
+                                tracing::info!(%peer, %namespace, %address, "Unroutable address in rejected registration");
+                            } else {
+                                tracing::info!(%peer, %namespace, %address, "Ignoring unroutable address of registration");
+                            }
+                        }
+
+                        if reject {
+                            tracing::info!(%peer, %namespace, unroutable=unroutable.len(), addresses=addresses.len(), "Rejected registration, unroutable addresses");
+                            self.reject(peer, namespace);
+
+                            continue;
+                        }
+                    }
+                    if !self.is_reported(&peer, &namespace)
+                        && self.is_full()
+                        && !(self.policy.evict_on_full && self.evict_oldest())
+                    {
+                        tracing::warn!(%peer, %namespace, max_total=?self.policy.max_total_registrations, "Rejected registration, reported registrations are at the cap");
+                        self.reject(peer, namespace);
+
+                        continue;
+                    }
+
+                    self.report(peer, namespace);
+
+                    return Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+                        Event::PeerRegistered { peer, registration },
+                    ));
+                }
+                // hidden registrations were never reported, so their expiry
+                // and removal aren't reported either
+                Event::RegistrationExpired(registration) => {
+                    if self.forget(
+                        registration.record.peer_id(),
+                        registration.namespace.to_string(),
+                    ) {
+                        return Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+                            Event::RegistrationExpired(registration),
+                        ));
+                    }
+                }
+                Event::PeerUnregistered { peer, namespace } => {
+                    if self.forget(peer, namespace.to_string()) {
+                        return Poll::Ready(NetworkBehaviourAction::GenerateEvent(
+                            Event::PeerUnregistered { peer, namespace },
+                        ));
+                    }
+                }
+                event => return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+    use libp2p::rendezvous::Config;
+
+    #[test]
+    fn is_routable() {
+        let cases = [
+            ("/ip4/1.1.1.1/tcp/4001", true),
+            ("/ip4/100.63.255.255/tcp/4001", true),
+            ("/ip4/100.128.0.0/tcp/4001", true),
+            ("/ip4/10.0.0.1/tcp/4001", false),
+            ("/ip4/172.16.0.1/tcp/4001", false),
+            ("/ip4/192.168.1.1/tcp/4001", false),
+            ("/ip4/127.0.0.1/tcp/4001", false),
+            ("/ip4/169.254.1.1/tcp/4001", false),
+            ("/ip4/0.0.0.0/tcp/4001", false),
+            ("/ip4/255.255.255.255/tcp/4001", false),
+            ("/ip4/192.0.2.1/tcp/4001", false),
+            ("/ip4/100.64.0.1/tcp/4001", false),
+            ("/ip4/100.127.255.255/tcp/4001", false),
+            ("/ip6/2001:4860:4860::8888/tcp/4001", true),
+            ("/ip6/::1/tcp/4001", false),
+            ("/ip6/::/tcp/4001", false),
+            ("/ip6/fc00::1/tcp/4001", false),
+            ("/ip6/fd12:3456::1/tcp/4001", false),
+            ("/ip6/fe80::1/tcp/4001", false),
+            ("/ip6/febf::1/tcp/4001", false),
+            ("/ip6/fec0::1/tcp/4001", true),
+            ("/ip6/::ffff:1.1.1.1/tcp/4001", true),
+            ("/ip6/::ffff:10.0.0.1/tcp/4001", false),
+            ("/ip6/::ffff:127.0.0.1/tcp/4001", false),
+            ("/ip6/::ffff:100.64.0.1/tcp/4001", false),
+            ("/dns4/example.com/tcp/4001", true),
+            ("/ip4/1.1.1.1/tcp/4001/ws", true),
+        ];
+
+        for (address, expected) in cases.iter() {
+            let address = address.parse::<Multiaddr>().unwrap();
+
+            assert_eq!(super::is_routable(&address), *expected, "{}", address);
+        }
+    }
+
+    #[test]
+    fn rejected_refresh_evicts_reported_registration() {
+        let inner = Rendezvous::new(Keypair::generate_ed25519(), Config::default());
+        let mut rendezvous =
+            FilteredRendezvous::new(inner, Policy::default(), Arc::new(AtomicBool::new(false)));
+        let peer = PeerId::random();
+
+        rendezvous.report(peer, "foo".to_owned());
+        rendezvous.reject(peer, "foo".to_owned());
+
+        assert_eq!(rendezvous.take_evicted(), vec![(peer, "foo".to_owned())]);
+        assert!(!rendezvous.is_reported(&peer, "foo"));
+        assert!(rendezvous.namespaces.is_empty());
+
+        // the expiry of the rejected refresh is not reported again
+        assert!(!rendezvous.forget(peer, "foo".to_owned()));
+        assert!(rendezvous.hidden.is_empty());
+        assert!(rendezvous.take_evicted().is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::event::ServerEvent;
+use crate::registrations::{self, unix_timestamp, Registrations};
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("rendezvous.v1");
+}
+
+use proto::registry_server::{Registry, RegistryServer};
+
+/// Number of events buffered per subscriber of `WatchEvents` before the
+/// oldest ones are dropped
+pub const EVENT_BUFFER: usize = 1024;
+
+/// Serves the tracked registrations and the rendezvous events through the
+/// `Registry` service defined in `proto/rendezvous.proto`.
+pub struct Service {
+    registrations: Arc<RwLock<Registrations>>,
+    events: broadcast::Sender<ServerEvent>,
+}
+
+impl Service {
+    pub fn new(
+        registrations: Arc<RwLock<Registrations>>,
+        events: broadcast::Sender<ServerEvent>,
+    ) -> Self {
+        Self {
+            registrations,
+            events,
+        }
+    }
+
+    /// Returns the active registrations matching `predicate`.
+    fn registrations(
+        &self,
+        predicate: impl Fn(&registrations::Registration) -> bool,
+    ) -> Vec<proto::Registration> {
+        let registrations = self
+            .registrations
+            .read()
+            .expect("registrations are not updated after a panic");
+        let now = unix_timestamp();
+
+        registrations
+            .active(now)
+            .filter(|registration| predicate(registration))
+            .map(|registration| proto::Registration {
+                peer_id: registration.peer_id.clone(),
+                namespace: registration.namespace.clone(),
+                addresses: registration
+                    .addresses
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                ttl: registration.ttl,
+                remaining_ttl: registration.remaining_ttl(now),
+                timestamp: registration.timestamp,
+                restored: registration.restored,
+            })
+            .collect()
+    }
+}
+
+#[tonic::async_trait]
+impl Registry for Service {
+    async fn list_registrations(
+        &self,
+        _: Request<proto::ListRegistrationsRequest>,
+    ) -> Result<Response<proto::ListRegistrationsResponse>, Status> {
+        Ok(Response::new(proto::ListRegistrationsResponse {
+            registrations: self.registrations(|_| true),
+        }))
+    }
+
+    async fn get_namespace(
+        &self,
+        request: Request<proto::GetNamespaceRequest>,
+    ) -> Result<Response<proto::Namespace>, Status> {
+        let namespace = request.into_inner().namespace;
+        let registrations = self.registrations(|registration| registration.namespace == namespace);
+
+        Ok(Response::new(proto::Namespace {
+            namespace,
+            registrations,
+        }))
+    }
+
+    type WatchEventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send + Sync + 'static>>;
+
+    async fn watch_events(
+        &self,
+        _: Request<proto::WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let events =
+            BroadcastStream::new(self.events.subscribe()).filter_map(|event| match event {
+                Ok(event) => Some(Ok(proto::Event::from(event))),
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    tracing::debug!(
+                        skipped,
+                        "gRPC event subscriber is lagging behind, dropped events"
+                    );
+                    None
+                }
+            });
+
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+impl From<ServerEvent> for proto::Event {
+    fn from(event: ServerEvent) -> Self {
+        use proto::event::Event;
+
+        let event = match event {
+            ServerEvent::PeerRegistered {
+                peer,
+                namespace,
+                addresses,
+                ttl,
+            } => Event::PeerRegistered(proto::PeerRegistered {
+                peer_id: peer.to_string(),
+                namespace,
+                addresses: addresses.iter().map(ToString::to_string).collect(),
+                ttl,
+            }),
+            ServerEvent::PeerUnregistered { peer, namespace } => {
+                Event::PeerUnregistered(proto::PeerUnregistered {
+                    peer_id: peer.to_string(),
+                    namespace,
+                })
+            }
+            ServerEvent::RegistrationExpired {
+                peer,
+                namespace,
+                addresses,
+                ttl,
+            } => Event::RegistrationExpired(proto::RegistrationExpired {
+                peer_id: peer.to_string(),
+                namespace,
+                addresses: addresses.iter().map(ToString::to_string).collect(),
+                ttl,
+            }),
+            ServerEvent::DiscoverServed { enquirer } => {
+                Event::DiscoverServed(proto::DiscoverServed {
+                    enquirer: enquirer.to_string(),
+                })
+            }
+        };
+
+        Self { event: Some(event) }
+    }
+}
+
+/// Binds `address` and serves `service` in a separate task. Requests have to
+/// carry `token` as bearer token in their `authorization` metadata, otherwise
+/// they are rejected with `UNAUTHENTICATED`.
+pub fn spawn_server(address: SocketAddr, token: String, service: Service) -> Result<()> {
+    let listener = std::net::TcpListener::bind(address)
+        .with_context(|| format!("Failed to bind gRPC server to {}", address))?;
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+
+    tracing::info!(%address, "Serving gRPC API");
+
+    tokio::spawn(async move {
+        let server = Server::builder()
+            .add_service(RegistryServer::with_interceptor(service, move |request| {
+                authenticate(&token, request)
+            }))
+            .serve_with_incoming(TcpListenerStream::new(listener));
+
+        if let Err(error) = server.await {
+            tracing::error!(%error, "gRPC server failed");
+        }
+    });
+
+    Ok(())
+}
+
+/// Passes `request` on if it carries `token` as bearer token, compared in
+/// constant time.
+fn authenticate(token: &str, request: Request<()>) -> Result<Request<()>, Status> {
+    let provided = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided)
+            if ring::constant_time::verify_slices_are_equal(
+                provided.as_bytes(),
+                token.as_bytes(),
+            )
+            .is_ok() =>
+        {
+            Ok(request)
+        }
+        _ => Err(Status::unauthenticated("Missing or invalid bearer token")),
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::{Context, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Binds an HTTP server to `address` and serves a readiness probe on `/health`
+/// in a separate task.
+///
+/// The probe responds with `200 OK` once `ready` is set and `503 Service
+/// Unavailable` before that.
+pub fn spawn_server(address: SocketAddr, ready: Arc<AtomicBool>) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let ready = ready.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(request, &ready);
+
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .with_context(|| format!("Failed to bind health server to {}", address))?
+        .serve(make_service);
+
+    tracing::info!(%address, "Serving health probe");
+
+    tokio::spawn(async move {
+        if let Err(error) = server.await {
+            tracing::error!(%error, "Health server failed");
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(request: Request<Body>, ready: &AtomicBool) -> Response<Body> {
+    let status = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/health") if ready.load(Ordering::SeqCst) => StatusCode::OK,
+        (&Method::GET, "/health") => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::NOT_FOUND,
+    };
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+
+    response
+}
@@ -0,0 +1,2344 @@
+mod admin;
+mod allow_list;
+mod audit;
+mod certificates;
+mod connection_age;
+mod connections;
+#[cfg(unix)]
+mod control_socket;
+mod effects;
+mod error;
+mod event;
+#[cfg(unix)]
+mod event_socket;
+mod filter;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
+mod log_file;
+mod metrics;
+mod mirror;
+mod observed_addresses;
+mod registration_log;
+mod registrations;
+mod summary;
+mod throttle;
+mod upstream;
+
+pub use crate::certificates::CertificateExpiry;
+#[cfg(unix)]
+pub use crate::control_socket::request_registrations;
+pub use crate::error::ServerError;
+pub use crate::event::ServerEvent;
+pub use crate::filter::{IdentifiedPeers, Policy, DEFAULT_MAX_ADDRESSES_PER_REGISTRATION};
+pub use crate::log_file::{LogWriter, RotatingFile, DEFAULT_LOG_MAX_FILES, DEFAULT_LOG_MAX_SIZE};
+
+use crate::admin::{Admin, Eviction, Info, ListenerReplacement, ProtocolsInfo, RendezvousInfo};
+use crate::allow_list::AllowList;
+use crate::audit::AuditLog;
+use crate::certificates::tls_config_from_params;
+use crate::connection_age::ConnectionAges;
+use crate::connections::Connections;
+use crate::effects::Effect;
+#[cfg(unix)]
+use crate::event_socket::EventSocket;
+use crate::filter::FilteredRendezvous;
+use crate::metrics::Metrics;
+use crate::observed_addresses::{ObservedAddresses, Promotion};
+use crate::registration_log::RegistrationLog;
+use crate::registrations::{unix_timestamp, Registrations};
+use crate::throttle::{DiscoverLimiter, ReconnectLimiter, RefreshLogThrottle};
+use crate::upstream::Upstream;
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{stream, AsyncRead, AsyncWrite, Stream, StreamExt};
+use ipnet::IpNet;
+use libp2p::core::connection::{ListenerId, PendingConnectionError};
+use libp2p::core::either::EitherTransport;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::timeout::TransportTimeout;
+use libp2p::core::transport::{Boxed, MemoryTransport, TransportError};
+use libp2p::core::upgrade::{InboundUpgrade, OutboundUpgrade, SelectUpgrade, UpgradeInfo, Version};
+use libp2p::core::{ConnectedPoint, Negotiated};
+use libp2p::dns::TokioDnsConfig;
+use libp2p::identify::{Identify, IdentifyConfig, IdentifyEvent};
+use libp2p::kad::record::store::MemoryStore;
+use libp2p::kad::{Kademlia, KademliaConfig, KademliaEvent};
+use libp2p::mplex::MplexConfig;
+use libp2p::multiaddr::Protocol;
+use libp2p::noise::{NoiseConfig, NoiseError, NoiseOutput, X25519Spec};
+use libp2p::ping::{Ping, PingConfig, PingEvent, PingSuccess};
+use libp2p::rendezvous::{Config, Event as RendezvousEvent, Rendezvous};
+use libp2p::swarm::toggle::Toggle;
+use libp2p::swarm::{AddressScore, ConnectionLimits, SwarmBuilder, SwarmEvent};
+use libp2p::tcp::TokioTcpConfig;
+use libp2p::websocket::{tls, WsConfig};
+use libp2p::yamux::YamuxConfig;
+use libp2p::{identity, noise, rendezvous, Multiaddr, PeerId, Swarm, Transport};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+#[cfg(feature = "grpc")]
+use tokio::sync::broadcast;
+use tokio::sync::mpsc;
+
+/// Protocol version advertised through the identify protocol
+const PROTOCOL_VERSION: &str = "/rendezvous-server/1.0.0";
+/// Protocol id of the rendezvous behaviour, which libp2p does not export
+const RENDEZVOUS_PROTOCOL: &str = "/rendezvous/1.0.0";
+
+/// Protocol name of the Kademlia DHT as reported through identify
+const KADEMLIA_PROTOCOL: &str = "/ipfs/kad/1.0.0";
+
+pub const DEFAULT_HANDSHAKE_TIMEOUT_SECS: u64 = 20;
+pub const DEFAULT_PING_INTERVAL_SECS: u64 = 86_400;
+pub const DEFAULT_MAX_ESTABLISHED_CONNECTIONS: u32 = 4096;
+pub const DEFAULT_MAX_PENDING_CONNECTIONS: u32 = 512;
+pub const DEFAULT_SUMMARY_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_CERT_EXPIRY_WARNING_DAYS: u64 = 14;
+pub const DEFAULT_METRICS_MAX_NAMESPACES: usize = 100;
+pub const DEFAULT_LISTEN_RETRIES: u32 = 5;
+pub const DEFAULT_LISTEN_RETRY_DELAY_SECS: u64 = 1;
+pub const DEFAULT_EVENT_BUFFER: usize = 1024;
+pub const DEFAULT_AUDIT_BUFFER_SIZE: usize = 1000;
+
+/// Interval at which the active registrations are written to the state file
+const STATE_FILE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time to wait after the first listen address is reported before logging
+/// all addresses the server is reachable at
+const LISTEN_ADDRESSES_SETTLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Time the self-test has to connect to one of the listen addresses
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval at which the allow peer file is checked for changes
+const ALLOW_PEER_FILE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval at which discover requests and connections leave the rate
+/// limiting windows
+const DECAY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval at which connections exceeding the maximum connection age are
+/// recycled
+const CONNECTION_AGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Settings of a [`RendezvousServer`].
+///
+/// Use [`ServerConfig::new`] to start from the defaults of the binary and
+/// override the fields as needed.
+pub struct ServerConfig {
+    /// Identity keypair of the rendezvous server
+    pub identity: identity::Keypair,
+    /// Port used for listening on TCP on all IPv4 interfaces
+    pub listen_tcp: Option<u16>,
+    /// Port used for listening on websocket on all IPv4 interfaces
+    pub listen_websocket: Option<u16>,
+    /// Additional multiaddresses to listen on
+    pub listen_addresses: Vec<Multiaddr>,
+    /// Port used for listening on the in-memory transport, e.g. for tests.
+    /// Cannot be combined with TCP or websocket listeners.
+    pub listen_memory: Option<u64>,
+    /// Additionally listen on IPv6 for `listen_tcp` and `listen_websocket`
+    pub listen_ip6: bool,
+    /// Number of times listening on an address is retried, e.g. while the
+    /// port is still in use by a previous instance
+    pub listen_retries: u32,
+    /// Delay between attempts to listen on an address
+    pub listen_retry_delay: Duration,
+    /// Delay before binding the listeners, during which the health endpoint
+    /// reports not ready
+    pub startup_delay: Option<Duration>,
+    /// Externally reachable multiaddresses announced to peers
+    pub external_addresses: Vec<Multiaddr>,
+    /// Announce the address most peers observed the server on, as reported
+    /// through identify, as external address
+    pub auto_external_address: bool,
+    /// Another rendezvous server to register this server with, has to end
+    /// with `/p2p/<peer id>`
+    pub upstream: Option<Multiaddr>,
+    /// Namespaces to register in with `upstream`
+    pub upstream_namespaces: Vec<String>,
+    /// Resolve DNS multiaddresses, e.g. `/dns4/example.com/tcp/443/wss`
+    pub dns: bool,
+    /// Paths to the private keys for secure websocket connections, one per
+    /// certificate
+    pub tls_private_keys: Vec<PathBuf>,
+    /// Paths to the certificates for secure websocket connections. Only the
+    /// first certificate is served, the others are validated.
+    pub tls_certificates: Vec<PathBuf>,
+    pub certificate_expiry: CertificateExpiry,
+    /// Minimum TTL in seconds that peers can request for their registrations
+    pub min_ttl: Option<u64>,
+    /// Maximum TTL in seconds that peers can request for their registrations
+    pub max_ttl: Option<u64>,
+    pub policy: Policy,
+    /// Path to a file with one peer id per line that are allowed to register.
+    /// Re-read on SIGHUP and when it changes.
+    pub allow_peer_file: Option<PathBuf>,
+    /// IP ranges connections are rejected from
+    pub denied_cidrs: Vec<IpNet>,
+    /// Maximum number of discover requests a peer can send per minute before
+    /// it is banned until its rate drops. Not limited if `None`.
+    pub max_discover_per_minute: Option<u32>,
+    /// Maximum number of connections a peer can establish per minute before
+    /// a warning is logged. Not limited if `None`.
+    pub max_reconnects_per_minute: Option<u32>,
+    /// Ban peers exceeding `max_reconnects_per_minute` for this long. Peers
+    /// are not banned if `None`.
+    pub reconnect_ban_duration: Option<Duration>,
+    pub protocols: Protocols,
+    /// Timeout for authenticating and multiplexing new connections
+    pub handshake_timeout: Duration,
+    /// Timeout for outgoing dials, e.g. to the upstream rendezvous server or
+    /// for the self-test, including the handshake
+    pub dial_timeout: Option<Duration>,
+    /// Handshake pattern used to authenticate connections. Clients have to
+    /// use the same pattern.
+    pub noise_pattern: NoisePattern,
+    pub muxer: Muxer,
+    pub yamux: YamuxSettings,
+    pub tcp_settings: TcpSettings,
+    /// Maximum number of concurrently established incoming connections
+    pub max_established_connections: u32,
+    /// Maximum number of concurrently pending incoming connections
+    pub max_pending_connections: u32,
+    /// Maximum number of concurrently pending outgoing connections
+    pub max_concurrent_dials: Option<u32>,
+    /// Socket address to serve Prometheus metrics on
+    pub metrics_addr: Option<SocketAddr>,
+    /// Maximum number of namespaces metrics are labeled with, further
+    /// namespaces are labeled as "other"
+    pub metrics_max_namespaces: usize,
+    /// Socket address to serve the /health readiness probe on
+    pub health_addr: Option<SocketAddr>,
+    /// Socket address to serve the admin API on. Requires `admin_token`.
+    pub admin_addr: Option<SocketAddr>,
+    /// Bearer token required by all requests to the admin API
+    pub admin_token: Option<String>,
+    /// Disconnect peers whose oldest connection is older than this to make
+    /// them dial again, e.g. through a load balancer
+    pub max_connection_age: Option<Duration>,
+    /// Don't disconnect peers with an active registration because of
+    /// `max_connection_age`
+    pub exempt_registered: bool,
+    /// Socket address to serve the gRPC API on. Requires the `grpc` feature
+    /// and `admin_token`.
+    pub grpc_addr: Option<SocketAddr>,
+    /// Dial the first reported listen address once to verify that
+    /// connections can be authenticated and multiplexed
+    pub self_test: bool,
+    /// Stop the server if the self-test fails. Implies `self_test`.
+    pub self_test_fatal: bool,
+    /// Path to a file the active registrations are persisted to and restored
+    /// from
+    pub state_file: Option<PathBuf>,
+    /// Interval at which a summary of the active registrations per namespace
+    /// is logged. The summary is disabled if `None`.
+    pub summary_interval: Option<Duration>,
+    /// Don't log served discover requests and connection events at info
+    /// level, registrations, warnings and errors are still logged
+    pub quiet: bool,
+    /// Window within which refreshes of a registration are logged only once,
+    /// the number of suppressed log lines is logged once per window
+    pub registration_log_throttle: Option<Duration>,
+    /// Number of handled swarm events whose logs, metrics and notifications
+    /// are buffered for the task processing them. Once the buffer is full,
+    /// notifications to the event socket and `events` are dropped until the
+    /// task caught up, the swarm is never blocked.
+    pub event_buffer: usize,
+    /// Number of recent rendezvous events kept in memory and served on `GET
+    /// /audit` by the admin API. 0 disables the audit log.
+    pub audit_buffer_size: usize,
+    /// Path of a Unix domain socket to stream rendezvous events to
+    #[cfg(unix)]
+    pub event_socket: Option<PathBuf>,
+    /// Path of a Unix domain socket other instances can request the active
+    /// registrations on, see [`request_registrations`]
+    #[cfg(unix)]
+    pub control_socket: Option<PathBuf>,
+    /// Path of a file registrations, unregistrations and expiries are
+    /// appended to as JSON lines
+    pub registration_log: Option<PathBuf>,
+    /// Size in bytes at which the registration log is rotated
+    pub registration_log_max_size: u64,
+    /// Sender each handled rendezvous event is forwarded to. Events are not
+    /// forwarded if `None`.
+    pub events: Option<mpsc::Sender<ServerEvent>>,
+    /// Address of a standby server the handled rendezvous events are streamed
+    /// to, see `mirror_from`
+    pub mirror_to: Option<SocketAddr>,
+    /// Socket address to receive the rendezvous events of a primary server on
+    /// and apply them to the tracked registrations
+    pub mirror_from: Option<SocketAddr>,
+    /// Secret the primary authenticates itself with to the standby. Required
+    /// by `mirror_to` and `mirror_from`.
+    pub mirror_secret: Option<String>,
+    /// Rejects new registrations while set, e.g. before a shutdown. Set on
+    /// SIGUSR1 and can be shared to start draining from the outside.
+    pub draining: Arc<AtomicBool>,
+}
+
+impl ServerConfig {
+    /// Creates a config for `identity` that does not listen on any address
+    /// yet.
+    pub fn new(identity: identity::Keypair) -> Self {
+        Self {
+            identity,
+            listen_tcp: None,
+            listen_websocket: None,
+            listen_addresses: Vec::new(),
+            listen_memory: None,
+            listen_ip6: false,
+            listen_retries: DEFAULT_LISTEN_RETRIES,
+            listen_retry_delay: Duration::from_secs(DEFAULT_LISTEN_RETRY_DELAY_SECS),
+            startup_delay: None,
+            external_addresses: Vec::new(),
+            auto_external_address: false,
+            upstream: None,
+            upstream_namespaces: Vec::new(),
+            dns: true,
+            tls_private_keys: Vec::new(),
+            tls_certificates: Vec::new(),
+            certificate_expiry: CertificateExpiry {
+                warning_threshold: Duration::from_secs(DEFAULT_CERT_EXPIRY_WARNING_DAYS * 86_400),
+                allow_expired: false,
+            },
+            min_ttl: None,
+            max_ttl: None,
+            policy: Policy::default(),
+            allow_peer_file: None,
+            denied_cidrs: Vec::new(),
+            max_discover_per_minute: None,
+            max_reconnects_per_minute: None,
+            reconnect_ban_duration: None,
+            protocols: Protocols {
+                ping: None,
+                keep_alive: None,
+                identify: false,
+                kademlia: false,
+            },
+            handshake_timeout: Duration::from_secs(DEFAULT_HANDSHAKE_TIMEOUT_SECS),
+            dial_timeout: None,
+            noise_pattern: NoisePattern::Xx,
+            muxer: Muxer::Both,
+            yamux: YamuxSettings::default(),
+            tcp_settings: TcpSettings::default(),
+            max_established_connections: DEFAULT_MAX_ESTABLISHED_CONNECTIONS,
+            max_pending_connections: DEFAULT_MAX_PENDING_CONNECTIONS,
+            max_concurrent_dials: None,
+            metrics_addr: None,
+            metrics_max_namespaces: DEFAULT_METRICS_MAX_NAMESPACES,
+            health_addr: None,
+            admin_addr: None,
+            admin_token: None,
+            max_connection_age: None,
+            exempt_registered: false,
+            grpc_addr: None,
+            self_test: false,
+            self_test_fatal: false,
+            state_file: None,
+            summary_interval: Some(Duration::from_secs(DEFAULT_SUMMARY_INTERVAL_SECS)),
+            quiet: false,
+            registration_log_throttle: None,
+            event_buffer: DEFAULT_EVENT_BUFFER,
+            audit_buffer_size: DEFAULT_AUDIT_BUFFER_SIZE,
+            #[cfg(unix)]
+            event_socket: None,
+            #[cfg(unix)]
+            control_socket: None,
+            registration_log: None,
+            registration_log_max_size: DEFAULT_LOG_MAX_SIZE,
+            events: None,
+            mirror_to: None,
+            mirror_from: None,
+            mirror_secret: None,
+            draining: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Summarizes the effective settings, leaving out the identity and the
+    /// admin token.
+    pub fn summary(&self) -> ConfigSummary {
+        let ws_or_wss = if self.tls_certificates.is_empty() {
+            "ws"
+        } else {
+            "wss"
+        };
+
+        let mut listen_addresses = Vec::new();
+        if let Some(port) = self.listen_memory {
+            listen_addresses.push(format!("/memory/{}", port));
+        }
+        if let Some(port) = self.listen_tcp {
+            listen_addresses.push(format!("/ip4/0.0.0.0/tcp/{}", port));
+            if self.listen_ip6 {
+                listen_addresses.push(format!("/ip6/::/tcp/{}", port));
+            }
+        }
+        if let Some(port) = self.listen_websocket {
+            listen_addresses.push(format!("/ip4/0.0.0.0/tcp/{}/{}", port, ws_or_wss));
+            if self.listen_ip6 {
+                listen_addresses.push(format!("/ip6/::/tcp/{}/{}", port, ws_or_wss));
+            }
+        }
+        listen_addresses.extend(self.listen_addresses.iter().map(Multiaddr::to_string));
+
+        ConfigSummary {
+            peer_id: self.identity.public().into_peer_id().to_string(),
+            listen_addresses,
+            external_addresses: self
+                .external_addresses
+                .iter()
+                .map(Multiaddr::to_string)
+                .collect(),
+            auto_external_address: self.auto_external_address,
+            upstream: self.upstream.as_ref().map(Multiaddr::to_string),
+            upstream_namespaces: self.upstream_namespaces.clone(),
+            websocket: self.listen_websocket.is_some()
+                || self.listen_addresses.iter().any(is_websocket),
+            tls: !self.tls_certificates.is_empty(),
+            dns: self.dns,
+            noise_pattern: self.noise_pattern.to_string(),
+            muxers: self.muxer.to_string(),
+            ping_interval_secs: self.protocols.ping.map(|ping| ping.interval.as_secs()),
+            keep_alive_secs: self.protocols.keep_alive.map(|interval| interval.as_secs()),
+            identify: self.protocols.identify,
+            kademlia: self.protocols.kademlia,
+            min_ttl: self.min_ttl,
+            max_ttl: self.max_ttl,
+            max_total_registrations: self.policy.max_total_registrations,
+            evict_on_full: self.policy.evict_on_full,
+            max_addresses_per_registration: self.policy.max_addresses_per_registration,
+            max_established_connections: self.max_established_connections,
+            max_pending_connections: self.max_pending_connections,
+            dial_timeout_secs: self.dial_timeout.map(|timeout| timeout.as_secs()),
+            max_concurrent_dials: self.max_concurrent_dials,
+            max_connection_age_secs: self.max_connection_age.map(|age| age.as_secs()),
+            metrics_addr: self.metrics_addr,
+            health_addr: self.health_addr,
+            admin_addr: self.admin_addr,
+            grpc_addr: self.grpc_addr,
+            state_file: self.state_file.clone(),
+            event_buffer: self.event_buffer,
+        }
+    }
+}
+
+/// Effective settings of a [`ServerConfig`], logged on startup.
+#[derive(Debug, Serialize)]
+pub struct ConfigSummary {
+    pub peer_id: String,
+    pub listen_addresses: Vec<String>,
+    pub external_addresses: Vec<String>,
+    pub auto_external_address: bool,
+    pub upstream: Option<String>,
+    pub upstream_namespaces: Vec<String>,
+    pub websocket: bool,
+    pub tls: bool,
+    pub dns: bool,
+    pub noise_pattern: String,
+    pub muxers: String,
+    pub ping_interval_secs: Option<u64>,
+    pub keep_alive_secs: Option<u64>,
+    pub identify: bool,
+    pub kademlia: bool,
+    pub min_ttl: Option<u64>,
+    pub max_ttl: Option<u64>,
+    pub max_total_registrations: Option<usize>,
+    pub evict_on_full: bool,
+    pub max_addresses_per_registration: Option<usize>,
+    pub max_established_connections: u32,
+    pub max_pending_connections: u32,
+    pub dial_timeout_secs: Option<u64>,
+    pub max_concurrent_dials: Option<u32>,
+    pub max_connection_age_secs: Option<u64>,
+    pub metrics_addr: Option<SocketAddr>,
+    pub health_addr: Option<SocketAddr>,
+    pub admin_addr: Option<SocketAddr>,
+    pub grpc_addr: Option<SocketAddr>,
+    pub state_file: Option<PathBuf>,
+    pub event_buffer: usize,
+}
+
+/// A rendezvous server whose swarm is set up but not yet listening.
+pub struct RendezvousServer {
+    swarm: Swarm<Behaviour>,
+    registrations: Registrations,
+    listen_tcp: Option<u16>,
+    listen_websocket: Option<u16>,
+    listen_addresses: Vec<Multiaddr>,
+    listen_memory: Option<u64>,
+    listen_ip6: bool,
+    listen_retries: u32,
+    listen_retry_delay: Duration,
+    startup_delay: Option<Duration>,
+    tls_private_keys: Vec<PathBuf>,
+    tls_certificates: Vec<PathBuf>,
+    certificate_expiry: CertificateExpiry,
+    websocket: bool,
+    secure_websocket: bool,
+    allow_list: Option<AllowList>,
+    identified_peers: IdentifiedPeers,
+    observed_addresses: Option<ObservedAddresses>,
+    upstream: Option<Upstream>,
+    max_discover_per_minute: Option<u32>,
+    max_reconnects_per_minute: Option<u32>,
+    reconnect_ban_duration: Option<Duration>,
+    max_connection_age: Option<Duration>,
+    exempt_registered: bool,
+    metrics_addr: Option<SocketAddr>,
+    metrics_max_namespaces: usize,
+    health_addr: Option<SocketAddr>,
+    admin: Option<(SocketAddr, String)>,
+    grpc: Option<(SocketAddr, String)>,
+    info: Info,
+    self_test: Option<SelfTest>,
+    state_file: Option<PathBuf>,
+    summary_interval: Option<Duration>,
+    quiet: bool,
+    registration_log_throttle: Option<Duration>,
+    event_buffer: usize,
+    audit_buffer_size: usize,
+    #[cfg(unix)]
+    event_socket: Option<PathBuf>,
+    #[cfg(unix)]
+    control_socket: Option<PathBuf>,
+    registration_log: Option<PathBuf>,
+    registration_log_max_size: u64,
+    events: Option<mpsc::Sender<ServerEvent>>,
+    mirror_to: Option<(SocketAddr, String)>,
+    mirror_from: Option<(SocketAddr, String)>,
+    draining: Arc<AtomicBool>,
+}
+
+impl RendezvousServer {
+    /// Loads the TLS configuration and persisted registrations and builds the
+    /// swarm.
+    ///
+    /// Nothing is bound until [`RendezvousServer::run`] is called.
+    pub async fn new(config: ServerConfig) -> Result<Self, ServerError> {
+        let summary = config.summary();
+
+        if config.listen_tcp.is_none()
+            && config.listen_websocket.is_none()
+            && config.listen_addresses.is_empty()
+            && config.listen_memory.is_none()
+        {
+            return Err(ServerError::Config("At least one listener is required, provide --listen-tcp, --listen-websocket, --listen-addr and/or --listen-memory".to_owned()));
+        }
+
+        let tcp =
+            config.listen_tcp.is_some() || config.listen_addresses.iter().any(|a| !is_websocket(a));
+        let websocket =
+            config.listen_websocket.is_some() || config.listen_addresses.iter().any(is_websocket);
+        let memory = config.listen_memory.is_some();
+
+        if memory && (tcp || websocket) {
+            return Err(ServerError::Config("--listen-memory cannot be combined with --listen-tcp, --listen-websocket or --listen-addr".to_owned()));
+        }
+
+        if config.event_buffer == 0 {
+            return Err(ServerError::Config(
+                "--event-buffer has to be greater than 0".to_owned(),
+            ));
+        }
+
+        if config
+            .yamux
+            .receive_window
+            .map_or(false, |window| window < MIN_YAMUX_RECEIVE_WINDOW)
+        {
+            return Err(ServerError::Config(format!(
+                "--yamux-receive-window has to be at least {} bytes",
+                MIN_YAMUX_RECEIVE_WINDOW
+            )));
+        }
+
+        if config.max_connection_age == Some(Duration::from_secs(0)) {
+            return Err(ServerError::Config(
+                "--max-connection-age has to be greater than 0".to_owned(),
+            ));
+        }
+        if config.exempt_registered && config.max_connection_age.is_none() {
+            return Err(ServerError::Config(
+                "--exempt-registered requires --max-connection-age".to_owned(),
+            ));
+        }
+
+        #[cfg(not(feature = "grpc"))]
+        if config.grpc_addr.is_some() {
+            return Err(ServerError::Config(
+                "--grpc-addr requires the server to be built with the grpc feature".to_owned(),
+            ));
+        }
+
+        let admin_token = config.admin_token.filter(|token| !token.is_empty());
+        let admin = match (config.admin_addr, admin_token.clone()) {
+            (Some(address), Some(token)) => Some((address, token)),
+            (Some(_), None) => {
+                return Err(ServerError::Config(
+                    "--admin-addr requires a non-empty --admin-token".to_owned(),
+                ))
+            }
+            (None, _) => None,
+        };
+        let grpc = match (config.grpc_addr, admin_token) {
+            (Some(address), Some(token)) => Some((address, token)),
+            (Some(_), None) => {
+                return Err(ServerError::Config(
+                    "--grpc-addr requires a non-empty --admin-token".to_owned(),
+                ))
+            }
+            (None, _) => None,
+        };
+
+        let mirror_secret = config.mirror_secret.filter(|secret| !secret.is_empty());
+        if (config.mirror_to.is_some() || config.mirror_from.is_some()) && mirror_secret.is_none() {
+            return Err(ServerError::Config(
+                "--mirror-to and --mirror-from require a non-empty --mirror-secret".to_owned(),
+            ));
+        }
+
+        let tls_config = tls_config_from_params(
+            config.tls_private_keys.clone(),
+            config.tls_certificates.clone(),
+            websocket,
+            config.certificate_expiry,
+        )
+        .await
+        .map_err(|error| ServerError::TlsConfig(error.into()))?;
+        let secure_websocket = tls_config.is_some();
+
+        if let (Some(min_ttl), Some(max_ttl)) = (config.min_ttl, config.max_ttl) {
+            if min_ttl > max_ttl {
+                return Err(ServerError::Config(format!(
+                    "--min-ttl ({}) must not be greater than --max-ttl ({})",
+                    min_ttl, max_ttl
+                )));
+            }
+        }
+
+        match serde_json::to_string(&summary) {
+            Ok(summary) => tracing::info!(config=%summary, "Effective configuration"),
+            Err(error) => tracing::warn!(%error, "Failed to encode effective configuration"),
+        }
+
+        tracing::info!(muxer=%config.muxer, "Offering stream multiplexers");
+        tracing::info!(pattern=%config.noise_pattern, "Authenticating connections with noise");
+        match (config.protocols.keep_alive, &config.protocols.ping) {
+            (Some(interval), ping) => {
+                tracing::info!(
+                    interval_secs = interval.as_secs(),
+                    "Keeping idle connections alive through pings"
+                );
+                if ping.is_some() {
+                    tracing::warn!(
+                        "--keep-alive overrides the ping interval and keepalive settings of --ping"
+                    );
+                }
+            }
+            (
+                None,
+                Some(PingSettings {
+                    interval,
+                    keep_alive,
+                }),
+            ) => {
+                // the ping behaviour is only composed as a workaround for
+                // clients that require the ping protocol
+                tracing::info!(interval_secs = interval.as_secs(), %keep_alive, "Ping enabled for compatibility, use --keep-alive to keep idle connections open");
+            }
+            (None, None) => {}
+        }
+
+        if (config.policy.require_identify || config.policy.required_agent_prefix.is_some())
+            && !config.protocols.identify
+        {
+            return Err(ServerError::Config(
+                "--require-identify and --require-agent-prefix require --identify".to_owned(),
+            ));
+        }
+        if config.policy.reject_private_addresses && config.policy.allow_partially_routable {
+            return Err(ServerError::Config(
+                "--reject-private-addresses and --allow-partially-routable cannot be combined"
+                    .to_owned(),
+            ));
+        }
+        if config.policy.evict_on_full && config.policy.max_total_registrations.is_none() {
+            return Err(ServerError::Config(
+                "--evict-on-full requires --max-total-registrations".to_owned(),
+            ));
+        }
+        if config.auto_external_address && !config.protocols.identify {
+            return Err(ServerError::Config(
+                "--auto-external-address requires --identify".to_owned(),
+            ));
+        }
+
+        let upstream = match config.upstream {
+            Some(address) => {
+                if config.external_addresses.is_empty() && !config.auto_external_address {
+                    tracing::warn!("Registering with the upstream rendezvous server requires an external address, provide --external-address or --auto-external-address");
+                }
+
+                let upstream = Upstream::new(address, config.upstream_namespaces)
+                    .map_err(|error| ServerError::Config(format!("{:#}", error)))?;
+
+                Some(upstream)
+            }
+            None if !config.upstream_namespaces.is_empty() => {
+                return Err(ServerError::Config(
+                    "--upstream-namespace requires --upstream".to_owned(),
+                ))
+            }
+            None => None,
+        };
+
+        let mut policy = config.policy;
+        let identified_peers = policy.identified_peers.clone();
+        let allow_list = match config.allow_peer_file {
+            Some(path) => {
+                let allow_list = AllowList::load(path)
+                    .await
+                    .map_err(|error| ServerError::AllowPeerFile(error.into()))?;
+                policy.allowed_peers_file = Some(allow_list.peers());
+
+                Some(allow_list)
+            }
+            None => None,
+        };
+
+        let self_test = if config.self_test || config.self_test_fatal {
+            // the client side of secure websockets is set up by the transport
+            let transport = create_transport(&identity::Keypair::generate_ed25519(), Transports {
+                tcp,
+                websocket,
+                memory,
+                tls: None,
+                dns: config.dns,
+                noise_pattern: config.noise_pattern,
+                handshake_timeout: config.handshake_timeout,
+                dial_timeout: config.dial_timeout,
+                muxer: config.muxer,
+                yamux: config.yamux,
+                tcp_settings: config.tcp_settings,
+                denied_cidrs: Vec::new(),
+            })?;
+
+            Some(SelfTest {
+                transport,
+                fatal: config.self_test_fatal,
+            })
+        } else {
+            None
+        };
+
+        let protocols = ProtocolsInfo {
+            ping: config.protocols.ping.is_some() || config.protocols.keep_alive.is_some(),
+            identify: config.protocols.identify,
+            kademlia: config.protocols.kademlia,
+            autonat: false,
+            relay: false,
+        };
+
+        let mut swarm = create_swarm(
+            config.identity,
+            config.protocols,
+            Transports {
+                tcp,
+                websocket,
+                memory,
+                tls: tls_config,
+                dns: config.dns,
+                noise_pattern: config.noise_pattern,
+                handshake_timeout: config.handshake_timeout,
+                dial_timeout: config.dial_timeout,
+                muxer: config.muxer,
+                yamux: config.yamux,
+                tcp_settings: config.tcp_settings,
+                denied_cidrs: config.denied_cidrs,
+            },
+            rendezvous_config(config.min_ttl, config.max_ttl),
+            policy,
+            config.draining.clone(),
+            ConnectionLimits::default()
+                .with_max_established_incoming(Some(config.max_established_connections))
+                .with_max_pending_incoming(Some(config.max_pending_connections))
+                .with_max_pending_outgoing(config.max_concurrent_dials),
+        )?;
+
+        tracing::info!(peer_id=%swarm.local_peer_id(), "Rendezvous server peer id");
+
+        let info = Info {
+            version: env!("CARGO_PKG_VERSION"),
+            peer_id: swarm.local_peer_id().to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            rendezvous: RendezvousInfo {
+                protocol: RENDEZVOUS_PROTOCOL,
+                min_ttl: config.min_ttl,
+                max_ttl: config.max_ttl,
+            },
+            protocols,
+        };
+
+        if !config.external_addresses.is_empty() {
+            tracing::info!(addresses=%Addresses(&config.external_addresses), "Announcing external addresses");
+        }
+        for address in config.external_addresses {
+            swarm.add_external_address(address, AddressScore::Infinite);
+        }
+
+        let registrations = match &config.state_file {
+            Some(state_file) => {
+                let registrations = Registrations::load(state_file)
+                    .await
+                    .map_err(|error| ServerError::StateFile(error.into()))?;
+                tracing::info!(
+                    count = registrations.len(),
+                    "Restored registrations from state file"
+                );
+
+                registrations
+            }
+            None => Registrations::default(),
+        };
+
+        Ok(Self {
+            swarm,
+            registrations,
+            listen_tcp: config.listen_tcp,
+            listen_websocket: config.listen_websocket,
+            listen_addresses: config.listen_addresses,
+            listen_memory: config.listen_memory,
+            listen_ip6: config.listen_ip6,
+            listen_retries: config.listen_retries,
+            listen_retry_delay: config.listen_retry_delay,
+            startup_delay: config.startup_delay,
+            tls_private_keys: config.tls_private_keys,
+            tls_certificates: config.tls_certificates,
+            certificate_expiry: config.certificate_expiry,
+            websocket,
+            secure_websocket,
+            allow_list,
+            identified_peers,
+            observed_addresses: config
+                .auto_external_address
+                .then(ObservedAddresses::default),
+            upstream,
+            max_discover_per_minute: config.max_discover_per_minute,
+            max_reconnects_per_minute: config.max_reconnects_per_minute,
+            reconnect_ban_duration: config.reconnect_ban_duration,
+            max_connection_age: config.max_connection_age,
+            exempt_registered: config.exempt_registered,
+            metrics_addr: config.metrics_addr,
+            metrics_max_namespaces: config.metrics_max_namespaces,
+            health_addr: config.health_addr,
+            admin,
+            grpc,
+            info,
+            self_test,
+            state_file: config.state_file,
+            summary_interval: config.summary_interval,
+            quiet: config.quiet,
+            registration_log_throttle: config.registration_log_throttle,
+            event_buffer: config.event_buffer,
+            audit_buffer_size: config.audit_buffer_size,
+            #[cfg(unix)]
+            event_socket: config.event_socket,
+            #[cfg(unix)]
+            control_socket: config.control_socket,
+            registration_log: config.registration_log,
+            registration_log_max_size: config.registration_log_max_size,
+            events: config.events,
+            mirror_to: config.mirror_to.zip(mirror_secret.clone()),
+            mirror_from: config.mirror_from.zip(mirror_secret),
+            draining: config.draining,
+        })
+    }
+
+    pub fn local_peer_id(&self) -> &PeerId {
+        self.swarm.local_peer_id()
+    }
+
+    /// Starts listening and drives the swarm until the process receives
+    /// SIGINT or SIGTERM.
+    pub async fn run(self) -> Result<(), ServerError> {
+        let Self {
+            mut swarm,
+            registrations,
+            listen_tcp,
+            listen_websocket,
+            listen_addresses,
+            listen_memory,
+            listen_ip6,
+            listen_retries,
+            listen_retry_delay,
+            startup_delay,
+            tls_private_keys,
+            tls_certificates,
+            certificate_expiry,
+            websocket,
+            secure_websocket,
+            mut allow_list,
+            identified_peers,
+            mut observed_addresses,
+            mut upstream,
+            max_discover_per_minute,
+            max_reconnects_per_minute,
+            reconnect_ban_duration,
+            max_connection_age,
+            exempt_registered,
+            metrics_addr,
+            metrics_max_namespaces,
+            health_addr,
+            admin,
+            grpc,
+            info,
+            mut self_test,
+            state_file,
+            summary_interval,
+            quiet,
+            registration_log_throttle,
+            event_buffer,
+            audit_buffer_size,
+            #[cfg(unix)]
+            event_socket,
+            #[cfg(unix)]
+            control_socket,
+            registration_log,
+            registration_log_max_size,
+            events,
+            mirror_to,
+            mirror_from,
+            draining,
+        } = self;
+
+        let registrations = Arc::new(RwLock::new(registrations));
+        let audit_log = Arc::new(RwLock::new(AuditLog::new(audit_buffer_size)));
+
+        let metrics = Arc::new(
+            Metrics::new(metrics_max_namespaces, metrics_addr.is_some()).map_err(|error| {
+                ServerError::Startup(error.context("Failed to initialize metrics").into())
+            })?,
+        );
+        if let Some(metrics_addr) = metrics_addr {
+            metrics::spawn_server(metrics_addr, metrics.clone())
+                .map_err(|error| ServerError::Startup(error.into()))?;
+        }
+
+        let ready = Arc::new(AtomicBool::new(false));
+        if let Some(health_addr) = health_addr {
+            health::spawn_server(health_addr, ready.clone())
+                .map_err(|error| ServerError::Startup(error.into()))?;
+        }
+
+        let connections = Arc::new(RwLock::new(Connections::default()));
+        let (eviction_sender, mut evictions) = mpsc::unbounded_channel();
+        let (replacement_sender, mut replacements) = mpsc::unbounded_channel();
+        if let Some((admin_addr, admin_token)) = admin {
+            admin::spawn_server(
+                admin_addr,
+                Arc::new(Admin::new(
+                    admin_token,
+                    info,
+                    registrations.clone(),
+                    connections.clone(),
+                    audit_log.clone(),
+                    eviction_sender,
+                    replacement_sender,
+                )),
+            )
+            .map_err(|error| ServerError::Startup(error.into()))?;
+        }
+
+        #[cfg(feature = "grpc")]
+        let grpc_events = match grpc {
+            Some((grpc_addr, grpc_token)) => {
+                let (sender, _) = broadcast::channel(grpc::EVENT_BUFFER);
+                grpc::spawn_server(
+                    grpc_addr,
+                    grpc_token,
+                    grpc::Service::new(registrations.clone(), sender.clone()),
+                )
+                .map_err(|error| ServerError::Startup(error.into()))?;
+
+                Some(sender)
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "grpc"))]
+        let _ = grpc;
+
+        #[cfg(unix)]
+        let event_socket = match &event_socket {
+            Some(path) => {
+                Some(EventSocket::bind(path).map_err(|error| ServerError::Startup(error.into()))?)
+            }
+            None => None,
+        };
+
+        #[cfg(unix)]
+        if let Some(path) = &control_socket {
+            control_socket::bind(path, registrations.clone())
+                .map_err(|error| ServerError::Startup(error.into()))?;
+        }
+
+        if let Some((mirror_from, mirror_secret)) = mirror_from {
+            mirror::spawn_receiver(mirror_from, mirror_secret, registrations.clone())
+                .await
+                .map_err(|error| ServerError::Startup(error.into()))?;
+        }
+
+        let registration_log = match registration_log {
+            Some(path) => Some(
+                RegistrationLog::open(path, registration_log_max_size)
+                    .map_err(|error| ServerError::Startup(error.into()))?,
+            ),
+            None => None,
+        };
+
+        let (mut effects, effect_handler) = effects::Handler {
+            metrics: metrics.clone(),
+            registrations: registrations.clone(),
+            audit_log,
+            summary_interval,
+            quiet,
+            refresh_log_throttle: registration_log_throttle.map(RefreshLogThrottle::new),
+            #[cfg(unix)]
+            event_socket,
+            registration_log,
+            events,
+            mirror: mirror_to
+                .map(|(address, secret)| mirror::spawn_sender(address, secret, event_buffer)),
+            #[cfg(feature = "grpc")]
+            grpc_events,
+        }
+        .spawn(event_buffer);
+
+        let ws_or_wss = if secure_websocket { "wss" } else { "ws" };
+
+        // the address each listener was requested on, to find the listener to
+        // replace through the admin API
+        let mut listeners = HashMap::new();
+
+        if let Some(delay) = startup_delay {
+            tracing::info!(
+                delay_secs = delay.as_secs(),
+                "Delaying startup, listeners are bound after the delay"
+            );
+            tokio::time::sleep(delay).await;
+            tracing::info!("Startup delay elapsed, binding listeners");
+        }
+
+        if let Some(memory_port) = listen_memory {
+            let address = Multiaddr::from(Protocol::Memory(memory_port));
+            let listener = listen_on(
+                &mut swarm,
+                address.clone(),
+                listen_retries,
+                listen_retry_delay,
+            )
+            .await
+            .map_err(|source| ServerError::Listen {
+                address: address.clone(),
+                source,
+            })?;
+            listeners.insert(listener, address);
+        }
+
+        if let Some(tcp_port) = listen_tcp {
+            let address = format!("/ip4/0.0.0.0/tcp/{}", tcp_port)
+                .parse::<Multiaddr>()
+                .expect("static string is valid MultiAddress");
+            let listener = listen_on(
+                &mut swarm,
+                address.clone(),
+                listen_retries,
+                listen_retry_delay,
+            )
+            .await
+            .map_err(|source| ServerError::Listen {
+                address: address.clone(),
+                source,
+            })?;
+            listeners.insert(listener, address);
+        }
+
+        if let Some(websocket_port) = listen_websocket {
+            let address = format!("/ip4/0.0.0.0/tcp/{}/{}", websocket_port, ws_or_wss)
+                .parse::<Multiaddr>()
+                .unwrap();
+            let listener = listen_on(
+                &mut swarm,
+                address.clone(),
+                listen_retries,
+                listen_retry_delay,
+            )
+            .await
+            .map_err(|source| ServerError::Listen {
+                address: address.clone(),
+                source,
+            })?;
+            listeners.insert(listener, address);
+        }
+
+        if listen_ip6 {
+            let ip6_addresses = listen_tcp
+                .map(|port| format!("/ip6/::/tcp/{}", port))
+                .into_iter()
+                .chain(listen_websocket.map(|port| format!("/ip6/::/tcp/{}/{}", port, ws_or_wss)));
+
+            for address in ip6_addresses {
+                let address = address
+                    .parse::<Multiaddr>()
+                    .expect("static string is valid MultiAddress");
+
+                match listen_on(
+                    &mut swarm,
+                    address.clone(),
+                    listen_retries,
+                    listen_retry_delay,
+                )
+                .await
+                {
+                    Ok(listener) => {
+                        listeners.insert(listener, address);
+                    }
+                    Err(error) => {
+                        tracing::warn!(%address, %error, "Failed to initialize IPv6 listener");
+                    }
+                }
+            }
+        }
+
+        for address in listen_addresses {
+            let listener = listen_on(
+                &mut swarm,
+                address.clone(),
+                listen_retries,
+                listen_retry_delay,
+            )
+            .await
+            .map_err(|source| ServerError::Listen {
+                address: address.clone(),
+                source,
+            })?;
+            listeners.insert(listener, address);
+        }
+
+        let mut state_file_interval = tokio::time::interval(STATE_FILE_INTERVAL);
+
+        let mut discover_limiter = max_discover_per_minute.map(DiscoverLimiter::new);
+        let mut reconnect_limiter = max_reconnects_per_minute
+            .map(|limit| ReconnectLimiter::new(limit, reconnect_ban_duration));
+        let mut decay_interval = tokio::time::interval(DECAY_INTERVAL);
+        let mut allow_peer_file_interval = tokio::time::interval(ALLOW_PEER_FILE_INTERVAL);
+        let mut connection_ages = max_connection_age.map(ConnectionAges::new);
+        let mut connection_age_interval = tokio::time::interval(CONNECTION_AGE_INTERVAL);
+
+        let shutdown = shutdown_signal();
+        tokio::pin!(shutdown);
+
+        let mut reload_signals = reload_signals().map_err(|error| {
+            ServerError::Startup(error.context("Failed to listen for reload signal").into())
+        })?;
+        let mut drain_signals = drain_signals().map_err(|error| {
+            ServerError::Startup(error.context("Failed to listen for drain signal").into())
+        })?;
+
+        let (self_test_sender, mut self_test_results) = mpsc::channel(1);
+        let mut self_test_fatal = false;
+
+        // all listen addresses are logged once shortly after the first one is
+        // reported, which gives the other listeners time to report theirs
+        let mut reachable_deadline = None;
+        let mut reachable_logged = false;
+
+        // new listeners that replace an old listener once they report a listen
+        // address
+        let mut pending_replacements = Vec::<PendingReplacement>::new();
+
+        // set if the server stops because it became unreachable
+        let mut failure = None;
+
+        loop {
+            tokio::select! {
+                result = &mut shutdown => {
+                    result.map_err(|error| {
+                        ServerError::Startup(error.context("Failed to listen for shutdown signal").into())
+                    })?;
+                    break;
+                }
+                swarm_event = swarm.select_next_some() => {
+                    // evicted to make room or replaced by a rejected refresh,
+                    // the latter doesn't cause a swarm event on its own
+                    for (peer, namespace) in swarm.behaviour_mut().rendezvous.take_evicted() {
+                        effects.submit(Effect::Evicted { peer, namespace, reported: true });
+                    }
+
+                    match swarm_event {
+                        SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::PeerRegistered {
+                            registration,
+                            ..
+                        })) => {
+                            effects.submit(Effect::Registered(registration));
+                        }
+                        SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::Registered {
+                            rendezvous_node,
+                            ttl,
+                            namespace,
+                        })) => {
+                            if let Some(upstream) = upstream.as_mut().filter(|upstream| upstream.peer() == rendezvous_node) {
+                                upstream.on_registered(&namespace, ttl);
+                            }
+                        }
+                        SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::RegisterFailed(error))) => {
+                            if let Some(upstream) = &mut upstream {
+                                upstream.on_register_failed(error);
+                            }
+                        }
+                        SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::PeerNotRegistered {
+                            peer,
+                            namespace,
+                            error,
+                        })) => {
+                            tracing::info!(%peer, %namespace, ?error, "Peer failed to register");
+                        }
+                        SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::RegistrationExpired(
+                            registration,
+                        ))) => {
+                            effects.submit(Effect::Expired(registration));
+                        }
+                        SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::PeerUnregistered {
+                            peer,
+                            namespace,
+                        })) => {
+                            effects.submit(Effect::Unregistered {
+                                peer,
+                                namespace: namespace.to_string(),
+                            });
+                        }
+                        SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::DiscoverServed {
+                            enquirer,
+                            registrations,
+                        })) => {
+                            effects.submit(Effect::DiscoverServed {
+                                enquirer,
+                                registrations,
+                            });
+                            if let Some(limiter) = &mut discover_limiter {
+                                if limiter.record(enquirer, Instant::now()) {
+                                    tracing::warn!(peer=%enquirer, limit=limiter.limit(), "Throttling peer, too many discover requests per minute");
+                                    swarm.ban_peer_id(enquirer);
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(Event::Identify(IdentifyEvent::Received {
+                            peer_id,
+                            info,
+                        })) => {
+                            tracing::debug!(peer=%peer_id, observed_address=%info.observed_addr, protocols=?info.protocols, agent_version=%info.agent_version, "Received identify info");
+                            identified_peers.insert(peer_id, info.agent_version.clone());
+
+                            if let Some(observed_addresses) = &mut observed_addresses {
+                                let remote_ip = connections
+                                    .read()
+                                    .expect("connections are not updated after a panic")
+                                    .get(&peer_id)
+                                    .last()
+                                    .and_then(|connection| ip_address(connection.endpoint.get_remote_address()));
+                                let promotion = remote_ip.and_then(|remote_ip| {
+                                    observed_addresses.record(peer_id, remote_ip, info.observed_addr.clone(), swarm.listeners())
+                                });
+                                if let Some(Promotion { address, ips, replaced }) = promotion {
+                                    if let Some(replaced) = replaced {
+                                        swarm.remove_external_address(&replaced);
+                                    }
+                                    tracing::info!(%address, %ips, "Announcing observed address as external address");
+                                    swarm.add_external_address(address, AddressScore::Infinite);
+                                }
+                            }
+
+                            // Kademlia only learns the addresses of peers it
+                            // dialed itself, inbound peers are added through
+                            // the listen addresses they report
+                            if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                                if info.protocols.iter().any(|protocol| protocol == KADEMLIA_PROTOCOL) {
+                                    for address in info.listen_addrs {
+                                        kademlia.add_address(&peer_id, address);
+                                    }
+                                }
+                            }
+                        }
+                        SwarmEvent::Behaviour(Event::Ping(PingEvent { peer, result: Ok(PingSuccess::Ping { rtt }) })) => {
+                            tracing::trace!(%peer, rtt_ms=rtt.as_millis() as u64, "Ping succeeded");
+                        }
+                        SwarmEvent::Behaviour(Event::Ping(PingEvent { peer, result: Err(error) })) => {
+                            // the ping behaviour keeps idle connections open,
+                            // a failed ping closes the connection
+                            tracing::warn!(%peer, %error, "Ping failed, connection is closed");
+                            metrics.ping_failure();
+                        }
+                        SwarmEvent::Behaviour(Event::Kademlia(KademliaEvent::RoutingUpdated {
+                            peer,
+                            addresses,
+                            is_new_peer,
+                            ..
+                        })) => {
+                            tracing::debug!(%peer, addresses=?addresses, %is_new_peer, "Kademlia routing table updated");
+                        }
+                        SwarmEvent::Behaviour(Event::Kademlia(KademliaEvent::UnroutablePeer { peer })) => {
+                            tracing::debug!(%peer, "Kademlia peer is unroutable, no known listen address");
+                        }
+                        SwarmEvent::Behaviour(Event::Kademlia(KademliaEvent::InboundRequestServed { request })) => {
+                            tracing::debug!(?request, "Kademlia request served");
+                        }
+                        SwarmEvent::IncomingConnectionError {
+                            send_back_addr,
+                            error: PendingConnectionError::ConnectionLimit(limit),
+                            ..
+                        } => {
+                            tracing::warn!(address=%send_back_addr, current=limit.current, limit=limit.limit, "Rejected incoming connection, connection limit reached");
+                            metrics.inbound_connection_error();
+                        }
+                        SwarmEvent::IncomingConnectionError {
+                            send_back_addr,
+                            error,
+                            ..
+                        } => {
+                            tracing::debug!(address=%send_back_addr, %error, "Incoming connection failed");
+                            metrics.inbound_connection_error();
+                        }
+                        SwarmEvent::ConnectionClosed { peer_id, endpoint, num_established, cause } => {
+                            match cause {
+                                None => tracing::debug!(peer=%peer_id, address=%endpoint.get_remote_address(), "Connection closed"),
+                                Some(error) => tracing::debug!(peer=%peer_id, address=%endpoint.get_remote_address(), %error, "Connection closed with error"),
+                            }
+
+                            connections
+                                .write()
+                                .expect("connections are not updated after a panic")
+                                .closed(&peer_id, &endpoint);
+                            if let Some(connection_ages) = &mut connection_ages {
+                                connection_ages.closed(&peer_id, &endpoint);
+                            }
+
+                            if num_established == 0 {
+                                identified_peers.remove(&peer_id);
+                                if let Some(observed_addresses) = &mut observed_addresses {
+                                    observed_addresses.remove(&peer_id);
+                                }
+                                if let Some(upstream) = upstream.as_mut().filter(|upstream| upstream.peer() == peer_id) {
+                                    upstream.on_connection_closed();
+                                }
+                            }
+                        }
+                        SwarmEvent::UnknownPeerUnreachableAddr { address, error } => {
+                            if let Some(upstream) = upstream.as_mut().filter(|upstream| *upstream.address() == address) {
+                                upstream.on_dial_failure(error);
+                            }
+                        }
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                            if let Some(upstream) = upstream.as_mut().filter(|upstream| upstream.peer() == peer_id) {
+                                upstream.on_connection_established(&mut swarm);
+                            }
+
+                            connections
+                                .write()
+                                .expect("connections are not updated after a panic")
+                                .established(peer_id, endpoint.clone());
+                            if let Some(connection_ages) = &mut connection_ages {
+                                connection_ages.established(peer_id, endpoint.clone(), Instant::now());
+                            }
+
+                            if let Some(limiter) = &mut reconnect_limiter {
+                                if limiter.record(peer_id, Instant::now()) {
+                                    match limiter.ban_duration() {
+                                        Some(ban_duration) => {
+                                            tracing::warn!(peer=%peer_id, limit=limiter.limit(), ban_secs=ban_duration.as_secs(), "Banning peer, too many connections per minute");
+                                            swarm.ban_peer_id(peer_id);
+                                        }
+                                        None => tracing::warn!(peer=%peer_id, limit=limiter.limit(), "Peer establishes too many connections per minute"),
+                                    }
+                                }
+                            }
+                        }
+                        SwarmEvent::ListenerError { listener_id, error } => {
+                            tracing::error!(listener=?listener_id, %error, "Listener failed");
+                        }
+                        SwarmEvent::ListenerClosed { listener_id, addresses, reason } => {
+                            // replaced listeners are no longer tracked when they close
+                            if listeners.remove(&listener_id).is_none() {
+                                tracing::debug!(listener=?listener_id, addresses=%Addresses(&addresses), "Replaced listener closed");
+                                continue;
+                            }
+
+                            match reason {
+                                Ok(()) => tracing::error!(listener=?listener_id, addresses=%Addresses(&addresses), "Listener closed"),
+                                Err(error) => tracing::error!(listener=?listener_id, addresses=%Addresses(&addresses), %error, "Listener closed with error"),
+                            }
+
+                            if pending_replacements.iter().any(|replacement| replacement.new == listener_id) {
+                                tracing::warn!(listener=?listener_id, "Replacing listener failed, keeping the old listener");
+                                pending_replacements.retain(|replacement| replacement.new != listener_id);
+                            }
+                            if listeners.is_empty() {
+                                failure = Some(anyhow!("All listeners closed, the server cannot be reached anymore"));
+                                break;
+                            }
+                        }
+                        SwarmEvent::NewListenAddr(address) => {
+                            let dialable = dialable_address(address.clone(), swarm.local_peer_id());
+                            tracing::info!(address=%dialable, "New listening address reported");
+
+                            if !reachable_logged && reachable_deadline.is_none() {
+                                reachable_deadline = Some(tokio::time::Instant::now() + LISTEN_ADDRESSES_SETTLE_DELAY);
+                            }
+                            ready.store(!draining.load(Ordering::SeqCst), Ordering::SeqCst);
+
+                            if let Some(index) = pending_replacements.iter().position(|replacement| is_listen_address_of(&replacement.address, &address)) {
+                                let replacement = pending_replacements.remove(index);
+                                if let Some(old_address) = listeners.remove(&replacement.old) {
+                                    let _ = swarm.remove_listener(replacement.old);
+
+                                    let active = swarm.listeners().cloned().collect::<Vec<_>>();
+                                    tracing::info!(from=%old_address, to=%replacement.address, listeners=%Addresses(&active), "Replaced listener");
+                                }
+                            }
+
+                            if let Some(SelfTest { transport, fatal }) = self_test.take() {
+                                self_test_fatal = fatal;
+
+                                let local_peer_id = *swarm.local_peer_id();
+                                let sender = self_test_sender.clone();
+                                tokio::spawn(async move {
+                                    let started = Instant::now();
+                                    let result = run_self_test(transport, address.clone(), local_peer_id).await;
+                                    let _ = sender.send((address, started.elapsed(), result)).await;
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ = effects.forward_backlog(), if effects.is_lagging() => {}
+                _ = tokio::time::sleep_until(upstream.as_ref().and_then(Upstream::deadline).unwrap_or_else(tokio::time::Instant::now)), if upstream.as_ref().and_then(Upstream::deadline).is_some() => {
+                    if let Some(upstream) = &mut upstream {
+                        upstream.on_deadline(&mut swarm);
+                    }
+                }
+                Some(ListenerReplacement { from, to, reply }) = replacements.recv() => {
+                    let old = listeners
+                        .iter()
+                        .find(|(_, address)| **address == from)
+                        .map(|(listener, _)| *listener);
+
+                    let result = match old {
+                        Some(old) => match swarm.listen_on(to.clone()) {
+                            Ok(new) => {
+                                tracing::info!(%from, %to, "Replacing listener, waiting for the new listener");
+                                listeners.insert(new, to.clone());
+                                pending_replacements.push(PendingReplacement { new, address: to, old });
+                                Ok(())
+                            }
+                            Err(error) => Err(format!("Failed to listen on {}: {}", to, error)),
+                        },
+                        None => Err(format!("No listener on {}", from)),
+                    };
+
+                    if let Err(error) = &result {
+                        tracing::warn!(%error, "Failed to replace listener");
+                    }
+                    let _ = reply.send(result);
+                }
+                Some(Eviction { peer, namespace }) = evictions.recv() => {
+                    // its expiry is not reported anymore, the metrics are updated now
+                    let reported = swarm.behaviour_mut().rendezvous.hide(peer, &namespace);
+                    effects.submit(Effect::Evicted { peer, namespace, reported });
+                    if swarm.disconnect_peer_id(peer).is_ok() && !quiet {
+                        tracing::info!(%peer, "Disconnected evicted peer");
+                    }
+                }
+                _ = tokio::time::sleep_until(reachable_deadline.unwrap_or_else(tokio::time::Instant::now)), if reachable_deadline.is_some() => {
+                    reachable_deadline = None;
+                    reachable_logged = true;
+
+                    let local_peer_id = *swarm.local_peer_id();
+                    let addresses = swarm
+                        .listeners()
+                        .map(|address| dialable_address(address.clone(), &local_peer_id))
+                        .collect::<Vec<_>>();
+                    tracing::info!(addresses=%Addresses(&addresses), "Server is reachable");
+                }
+                Some((address, elapsed, result)) = self_test_results.recv() => {
+                    let elapsed_ms = elapsed.as_millis() as u64;
+                    match result {
+                        Ok(()) => tracing::info!(%address, elapsed_ms, "Self-test connected to the server"),
+                        Err(error) if self_test_fatal => {
+                            tracing::error!(%address, elapsed_ms, ?error, "Self-test failed to connect to the server");
+                            failure = Some(error.context("Self-test failed"));
+                            break;
+                        }
+                        Err(error) => tracing::warn!(%address, elapsed_ms, ?error, "Self-test failed to connect to the server"),
+                    }
+                }
+                Some(()) = drain_signals.next() => {
+                    if !draining.swap(true, Ordering::SeqCst) {
+                        tracing::info!("Draining, rejecting new registrations and reporting not ready");
+                    }
+                    ready.store(false, Ordering::SeqCst);
+                }
+                Some(()) = reload_signals.next() => {
+                    let tls_config = tls_config_from_params(
+                        tls_private_keys.clone(),
+                        tls_certificates.clone(),
+                        websocket,
+                        certificate_expiry,
+                    )
+                    .await;
+
+                    match tls_config {
+                        Ok(Some(_)) => {
+                            // the TLS config is baked into the websocket transport
+                            // and cannot be swapped on a running swarm
+                            tracing::info!("Reloaded TLS certificate and private key successfully, restart the server to serve them");
+                        }
+                        Ok(None) => tracing::debug!("No TLS certificate configured, nothing to reload"),
+                        Err(error) => tracing::warn!(?error, "Failed to reload TLS certificate and private key"),
+                    }
+
+                    if let Some(allow_list) = &mut allow_list {
+                        if let Err(error) = allow_list.reload().await {
+                            tracing::warn!(?error, "Failed to reload allow peer file");
+                        }
+                    }
+                }
+                _ = allow_peer_file_interval.tick(), if allow_list.is_some() => {
+                    if let Some(allow_list) = &mut allow_list {
+                        if let Err(error) = allow_list.reload_if_modified().await {
+                            tracing::warn!(?error, "Failed to reload allow peer file");
+                        }
+                    }
+                }
+                _ = decay_interval.tick(), if discover_limiter.is_some() || reconnect_limiter.is_some() => {
+                    let now = Instant::now();
+
+                    // a peer stays banned as long as one of the limiters bans it
+                    if let Some(limiter) = &mut discover_limiter {
+                        for peer in limiter.decay(now) {
+                            if !quiet {
+                                tracing::info!(%peer, "Discover limit of peer reset");
+                            }
+                            if !reconnect_limiter.as_ref().map_or(false, |limiter| limiter.is_banned(&peer)) {
+                                swarm.unban_peer_id(peer);
+                            }
+                        }
+                    }
+                    if let Some(limiter) = &mut reconnect_limiter {
+                        let banning = limiter.ban_duration().is_some();
+                        for peer in limiter.decay(now) {
+                            if !quiet {
+                                tracing::info!(%peer, "Connection limit of peer reset");
+                            }
+                            if banning && !discover_limiter.as_ref().map_or(false, |limiter| limiter.is_throttled(&peer)) {
+                                swarm.unban_peer_id(peer);
+                            }
+                        }
+                    }
+                }
+                _ = connection_age_interval.tick(), if connection_ages.is_some() => {
+                    if let Some(connection_ages) = &mut connection_ages {
+                        let registered = if exempt_registered {
+                            registrations
+                                .read()
+                                .expect("registrations are not updated after a panic")
+                                .active(unix_timestamp())
+                                .filter(|registration| !registration.restored)
+                                .map(|registration| registration.peer_id.clone())
+                                .collect::<HashSet<_>>()
+                        } else {
+                            HashSet::new()
+                        };
+
+                        for (peer, connections) in connection_ages.expired(Instant::now(), |peer| registered.contains(&peer.to_string())) {
+                            for (endpoint, age) in connections {
+                                tracing::info!(%peer, address=%endpoint.get_remote_address(), age_secs=age.as_secs(), "Recycling connection, maximum connection age exceeded");
+                            }
+                            let _ = swarm.disconnect_peer_id(peer);
+                        }
+                    }
+                }
+                _ = state_file_interval.tick(), if state_file.is_some() => {
+                    if let Some(state_file) = &state_file {
+                        // save a snapshot to not hold the lock while writing
+                        let snapshot = registrations
+                            .read()
+                            .expect("registrations are not updated after a panic")
+                            .clone();
+                        if let Err(error) = snapshot.save(state_file).await {
+                            tracing::warn!(?error, "Failed to persist registrations");
+                        }
+                    }
+                }
+            }
+        }
+
+        // the tracked registrations are complete once all effects are handled
+        effects.close(effect_handler).await;
+
+        let registrations = registrations
+            .read()
+            .expect("registrations are not updated after a panic")
+            .clone();
+
+        tracing::info!(
+            active_registrations = registrations.len(),
+            "Shutting down rendezvous server"
+        );
+
+        if let Some(state_file) = &state_file {
+            registrations
+                .save(state_file)
+                .await
+                .map_err(|error| ServerError::StateFile(error.into()))?;
+        }
+
+        drop(swarm);
+
+        match failure {
+            Some(error) => Err(ServerError::Stopped(error.into())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Listener started through the admin API to replace `old` once it listens
+struct PendingReplacement {
+    new: ListenerId,
+    /// Address `new` was started on
+    address: Multiaddr,
+    old: ListenerId,
+}
+
+/// Throwaway client transport used to dial the server once it listens
+struct SelfTest {
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    fatal: bool,
+}
+
+/// Dials `address` through `transport` and checks that the connection is
+/// authenticated as `local_peer_id`.
+async fn run_self_test(
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    address: Multiaddr,
+    local_peer_id: PeerId,
+) -> Result<()> {
+    let dial = transport
+        .dial(address)
+        .context("Failed to dial listen address")?;
+    let (peer, _muxer) = tokio::time::timeout(SELF_TEST_TIMEOUT, dial)
+        .await
+        .context("Timed out connecting to listen address")?
+        .context("Failed to connect to listen address")?;
+
+    if peer != local_peer_id {
+        bail!("Connected to peer {} instead of {}", peer, local_peer_id);
+    }
+
+    Ok(())
+}
+
+/// Resolves once the process receives SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = sigterm.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+/// Stream of requests to reload the TLS configuration, triggered by SIGHUP on
+/// Unix.
+fn reload_signals() -> Result<Pin<Box<dyn Stream<Item = ()> + Send>>> {
+    #[cfg(unix)]
+    return signals(SignalKind::hangup());
+
+    #[cfg(not(unix))]
+    Ok(Box::pin(stream::pending()))
+}
+
+/// Stream of requests to stop accepting new registrations, triggered by
+/// SIGUSR1 on Unix.
+fn drain_signals() -> Result<Pin<Box<dyn Stream<Item = ()> + Send>>> {
+    #[cfg(unix)]
+    return signals(SignalKind::user_defined1());
+
+    #[cfg(not(unix))]
+    Ok(Box::pin(stream::pending()))
+}
+
+#[cfg(unix)]
+fn signals(kind: SignalKind) -> Result<Pin<Box<dyn Stream<Item = ()> + Send>>> {
+    let signal = signal(kind)?;
+
+    Ok(Box::pin(stream::unfold(signal, |mut signal| async move {
+        signal.recv().await.map(|()| ((), signal))
+    })))
+}
+
+/// Extracts the IP address of a multiaddress, if it has one.
+fn ip_address(address: &Multiaddr) -> Option<IpAddr> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+/// Starts listening on `address`, retrying up to `retries` times if binding
+/// fails, e.g. because the port is still in use by a previous instance.
+async fn listen_on(
+    swarm: &mut Swarm<Behaviour>,
+    address: Multiaddr,
+    retries: u32,
+    delay: Duration,
+) -> Result<ListenerId, TransportError<std::io::Error>> {
+    let mut attempt = 0;
+
+    loop {
+        match swarm.listen_on(address.clone()) {
+            Ok(listener) => return Ok(listener),
+            Err(TransportError::Other(error)) if attempt < retries => {
+                attempt += 1;
+                tracing::warn!(%address, %error, attempt, retries, "Failed to listen, retrying");
+                tokio::time::sleep(delay).await;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Whether a listener started on `requested` listens on `reported`, i.e. all
+/// protocols match except for unspecified IP addresses and port 0 in
+/// `requested`.
+fn is_listen_address_of(requested: &Multiaddr, reported: &Multiaddr) -> bool {
+    requested.iter().count() == reported.iter().count()
+        && requested
+            .iter()
+            .zip(reported.iter())
+            .all(|(requested, reported)| match (requested, reported) {
+                (Protocol::Ip4(requested), Protocol::Ip4(_)) if requested.is_unspecified() => true,
+                (Protocol::Ip6(requested), Protocol::Ip6(_)) if requested.is_unspecified() => true,
+                (Protocol::Tcp(0), Protocol::Tcp(_)) => true,
+                (Protocol::Memory(0), Protocol::Memory(_)) => true,
+                (requested, reported) => requested == reported,
+            })
+}
+
+/// Appends `/p2p/<peer_id>` to `address` so clients can dial it directly.
+fn dialable_address(address: Multiaddr, peer_id: &PeerId) -> Multiaddr {
+    address.with(Protocol::P2p((*peer_id).into()))
+}
+
+fn is_websocket(address: &Multiaddr) -> bool {
+    address
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::Ws(_) | Protocol::Wss(_)))
+}
+
+pub fn create_swarm(
+    identity: identity::Keypair,
+    protocols: Protocols,
+    transports: Transports,
+    rendezvous_config: Config,
+    policy: Policy,
+    draining: Arc<AtomicBool>,
+    limits: ConnectionLimits,
+) -> Result<Swarm<Behaviour>, ServerError> {
+    let local_public_key = identity.public();
+    let local_peer_id = local_public_key.clone().into_peer_id();
+
+    let transport = create_transport(&identity, transports)?;
+    let rendezvous = FilteredRendezvous::new(
+        Rendezvous::new(identity, rendezvous_config),
+        policy,
+        draining,
+    );
+    let swarm = SwarmBuilder::new(
+        transport,
+        Behaviour::new(rendezvous, protocols, local_public_key),
+        local_peer_id,
+    )
+    .executor(Box::new(|f| {
+        tokio::spawn(f);
+    }))
+    .connection_limits(limits)
+    .build();
+
+    Ok(swarm)
+}
+
+pub fn rendezvous_config(min_ttl: Option<u64>, max_ttl: Option<u64>) -> Config {
+    let mut config = Config::default();
+
+    if let Some(min_ttl) = min_ttl {
+        config = config.with_min_ttl(min_ttl);
+    }
+    if let Some(max_ttl) = max_ttl {
+        config = config.with_max_ttl(max_ttl);
+    }
+
+    config
+}
+
+pub fn create_transport(
+    identity: &identity::Keypair,
+    transports: Transports,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, ServerError> {
+    let Transports {
+        tcp,
+        websocket,
+        memory,
+        tls,
+        dns,
+        noise_pattern,
+        handshake_timeout,
+        dial_timeout,
+        muxer,
+        yamux,
+        tcp_settings,
+        denied_cidrs,
+    } = transports;
+
+    let nodelay = tcp_settings.nodelay.unwrap_or(true);
+    tracing::debug!(
+        %tcp,
+        %websocket,
+        %memory,
+        tls = %tls.is_some(),
+        %dns,
+        %nodelay,
+        reuse_port = %tcp_settings.reuse_port,
+        backlog = ?tcp_settings.backlog,
+        %noise_pattern,
+        %muxer,
+        dial_timeout_secs = ?dial_timeout.map(|timeout| timeout.as_secs()),
+        "Creating transport"
+    );
+
+    if memory {
+        if tcp || websocket {
+            return Err(ServerError::TransportBuild(
+                "The memory transport cannot be combined with TCP or websocket transports".into(),
+            ));
+        }
+
+        let transport = authenticate_and_multiplex(
+            MemoryTransport::default().boxed(),
+            &identity,
+            noise_pattern,
+            handshake_timeout,
+            muxer,
+            yamux,
+            Vec::new(),
+        )?;
+
+        return Ok(with_dial_timeout(transport, dial_timeout));
+    }
+
+    let mut tcp_config = TokioTcpConfig::new()
+        .nodelay(nodelay)
+        .port_reuse(tcp_settings.reuse_port);
+    if let Some(backlog) = tcp_settings.backlog {
+        tcp_config = tcp_config.listen_backlog(backlog);
+    }
+    let tcp_transport = if dns {
+        EitherTransport::Left(TokioDnsConfig::system(tcp_config).unwrap())
+    } else {
+        EitherTransport::Right(tcp_config)
+    };
+
+    let websocket_transport = websocket.then(|| {
+        let mut websocket_transport = WsConfig::new(tcp_transport.clone());
+
+        if let Some(tls) = tls {
+            websocket_transport.set_tls_config(tls);
+        }
+
+        websocket_transport
+    });
+
+    let transport = match (tcp, websocket_transport) {
+        (true, Some(websocket_transport)) => authenticate_and_multiplex(
+            tcp_transport.or_transport(websocket_transport).boxed(),
+            &identity,
+            noise_pattern,
+            handshake_timeout,
+            muxer,
+            yamux,
+            denied_cidrs,
+        )?,
+        (true, None) => authenticate_and_multiplex(
+            tcp_transport.boxed(),
+            &identity,
+            noise_pattern,
+            handshake_timeout,
+            muxer,
+            yamux,
+            denied_cidrs,
+        )?,
+        (false, Some(websocket_transport)) => authenticate_and_multiplex(
+            websocket_transport.boxed(),
+            &identity,
+            noise_pattern,
+            handshake_timeout,
+            muxer,
+            yamux,
+            denied_cidrs,
+        )?,
+        (false, None) => {
+            return Err(ServerError::TransportBuild(
+                "At least one of TCP or websocket transport has to be enabled".into(),
+            ))
+        }
+    };
+
+    Ok(with_dial_timeout(transport, dial_timeout))
+}
+
+/// Bounds outgoing dials of `transport` by `timeout`, incoming connections
+/// are only bounded by the handshake timeout.
+fn with_dial_timeout(
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    timeout: Option<Duration>,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    match timeout {
+        Some(timeout) => TransportTimeout::with_outgoing_timeout(transport, timeout).boxed(),
+        None => transport,
+    }
+}
+
+/// Rejects incoming connections of `transport` from `denied_cidrs` before
+/// the security handshake, only the offending connection is closed. Outgoing
+/// dials are not affected.
+fn reject_denied_cidrs<T>(transport: Boxed<T>, denied_cidrs: Vec<IpNet>) -> Boxed<T>
+where
+    T: Send + 'static,
+{
+    if denied_cidrs.is_empty() {
+        return transport;
+    }
+
+    transport
+        .and_then(move |connection, endpoint| {
+            let denied = match &endpoint {
+                ConnectedPoint::Listener { send_back_addr, .. } => ip_address(send_back_addr)
+                    .and_then(|ip| denied_cidrs.iter().find(|cidr| cidr.contains(&ip)))
+                    .map(|cidr| (send_back_addr.clone(), *cidr)),
+                ConnectedPoint::Dialer { .. } => None,
+            };
+
+            async move {
+                match denied {
+                    Some((address, cidr)) => {
+                        tracing::info!(%address, %cidr, "Rejected connection from denied IP range");
+
+                        Err(std::io::Error::new(
+                            std::io::ErrorKind::ConnectionRefused,
+                            format!("{} is in the denied IP range {}", address, cidr),
+                        ))
+                    }
+                    None => Ok(connection),
+                }
+            }
+        })
+        .boxed()
+}
+
+fn authenticate_and_multiplex<T>(
+    transport: Boxed<T>,
+    identity: &identity::Keypair,
+    noise_pattern: NoisePattern,
+    timeout: Duration,
+    muxer: Muxer,
+    yamux_settings: YamuxSettings,
+    denied_cidrs: Vec<IpNet>,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, ServerError>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let transport = reject_denied_cidrs(transport, denied_cidrs);
+
+    let noise_identity = noise::Keypair::<X25519Spec>::new()
+        .into_authentic(identity)
+        .map_err(|error| ServerError::TransportBuild(error.into()))?;
+
+    let mut yamux_config = YamuxConfig::default();
+    if let Some(max_buffer_size) = yamux_settings.max_buffer_size {
+        yamux_config.set_max_buffer_size(max_buffer_size);
+    }
+    if let Some(receive_window) = yamux_settings.receive_window {
+        yamux_config.set_receive_window_size(receive_window);
+    }
+    tracing::debug!(config=?yamux_config, "Effective yamux configuration");
+
+    let transport = match noise_pattern {
+        NoisePattern::Xx => multiplex(
+            transport,
+            NoiseConfig::xx(noise_identity).into_authenticated(),
+            timeout,
+            muxer,
+            yamux_config,
+        ),
+        NoisePattern::Ix => multiplex(
+            transport,
+            NoiseConfig::ix(noise_identity).into_authenticated(),
+            timeout,
+            muxer,
+            yamux_config,
+        ),
+        // the dialer of an IK handshake has to know the static key of the
+        // listener upfront, which a transport used for both directions cannot
+        NoisePattern::Ik => return Err(ServerError::TransportBuild("The noise IK pattern requires the static key of the remote peer and is not supported, use xx or ix".into())),
+    };
+
+    Ok(transport)
+}
+
+/// Authenticates connections of `transport` with `auth_upgrade` and
+/// negotiates `muxer` on top.
+fn multiplex<T, U>(
+    transport: Boxed<T>,
+    auth_upgrade: U,
+    timeout: Duration,
+    muxer: Muxer,
+    yamux_config: YamuxConfig,
+) -> Boxed<(PeerId, StreamMuxerBox)>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    U: InboundUpgrade<
+            Negotiated<T>,
+            Output = (PeerId, NoiseOutput<Negotiated<T>>),
+            Error = NoiseError,
+        > + OutboundUpgrade<
+            Negotiated<T>,
+            Output = (PeerId, NoiseOutput<Negotiated<T>>),
+            Error = NoiseError,
+        > + Clone
+        + Send
+        + Sync
+        + 'static,
+    <U as UpgradeInfo>::Info: Send,
+    <<U as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send,
+    <U as InboundUpgrade<Negotiated<T>>>::Future: Send,
+    <U as OutboundUpgrade<Negotiated<T>>>::Future: Send,
+{
+    let authenticated = transport.upgrade(Version::V1).authenticate(auth_upgrade);
+
+    match muxer {
+        Muxer::Yamux => authenticated
+            .multiplex(yamux_config)
+            .timeout(timeout)
+            .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+            .boxed(),
+        Muxer::Mplex => authenticated
+            .multiplex(MplexConfig::new())
+            .timeout(timeout)
+            .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+            .boxed(),
+        Muxer::Both => authenticated
+            .multiplex(SelectUpgrade::new(yamux_config, MplexConfig::new()))
+            .timeout(timeout)
+            .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+            .boxed(),
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Rendezvous(rendezvous::Event),
+    Ping(PingEvent),
+    Identify(IdentifyEvent),
+    Kademlia(KademliaEvent),
+}
+
+impl From<rendezvous::Event> for Event {
+    fn from(event: rendezvous::Event) -> Self {
+        Event::Rendezvous(event)
+    }
+}
+
+impl From<PingEvent> for Event {
+    fn from(event: PingEvent) -> Self {
+        Event::Ping(event)
+    }
+}
+
+impl From<IdentifyEvent> for Event {
+    fn from(event: IdentifyEvent) -> Self {
+        Event::Identify(event)
+    }
+}
+
+impl From<KademliaEvent> for Event {
+    fn from(event: KademliaEvent) -> Self {
+        Event::Kademlia(event)
+    }
+}
+
+/// Transports the server listens on
+pub struct Transports {
+    pub tcp: bool,
+    pub websocket: bool,
+    /// In-memory transport, mutually exclusive with `tcp` and `websocket`
+    pub memory: bool,
+    /// TLS configuration for secure websockets
+    pub tls: Option<tls::Config>,
+    /// Resolve DNS multiaddresses of the TCP and websocket transports
+    pub dns: bool,
+    pub noise_pattern: NoisePattern,
+    /// Timeout for authenticating and multiplexing new connections
+    pub handshake_timeout: Duration,
+    /// Timeout for outgoing dials, including the handshake. Outgoing dials
+    /// are only bounded by `handshake_timeout` if `None`.
+    pub dial_timeout: Option<Duration>,
+    pub muxer: Muxer,
+    pub yamux: YamuxSettings,
+    pub tcp_settings: TcpSettings,
+    /// IP ranges incoming connections are rejected from before the security
+    /// handshake
+    pub denied_cidrs: Vec<IpNet>,
+}
+
+/// Smallest receive window yamux accepts, smaller windows make it panic
+pub const MIN_YAMUX_RECEIVE_WINDOW: u32 = 256 * 1024;
+
+/// Overrides of the yamux defaults
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YamuxSettings {
+    pub max_buffer_size: Option<usize>,
+    /// At least [`MIN_YAMUX_RECEIVE_WINDOW`]
+    pub receive_window: Option<u32>,
+}
+
+/// Socket options of the TCP transport, also used by websockets
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpSettings {
+    /// Size of the accept backlog of listening sockets. Defaults to 1024.
+    pub backlog: Option<u32>,
+    /// Set `TCP_NODELAY`. Defaults to `true`.
+    pub nodelay: Option<bool>,
+    /// Set `SO_REUSEADDR` and, on Unix, `SO_REUSEPORT` so multiple processes
+    /// can listen on the same port. Outgoing connections are made from the
+    /// listening port as well.
+    pub reuse_port: bool,
+}
+
+/// Stream multiplexers offered to peers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Muxer {
+    Yamux,
+    Mplex,
+    Both,
+}
+
+impl FromStr for Muxer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "yamux" => Ok(Muxer::Yamux),
+            "mplex" => Ok(Muxer::Mplex),
+            "both" => Ok(Muxer::Both),
+            _ => bail!("Unknown muxer {}, expected one of yamux, mplex or both", s),
+        }
+    }
+}
+
+impl fmt::Display for Muxer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Muxer::Yamux => write!(f, "yamux"),
+            Muxer::Mplex => write!(f, "mplex"),
+            Muxer::Both => write!(f, "yamux,mplex"),
+        }
+    }
+}
+
+/// Handshake pattern of the noise protocol used to authenticate connections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoisePattern {
+    Xx,
+    Ix,
+    /// Requires knowing the static key of the remote peer before dialing,
+    /// which is not supported
+    Ik,
+}
+
+impl FromStr for NoisePattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "xx" => Ok(NoisePattern::Xx),
+            "ix" => Ok(NoisePattern::Ix),
+            "ik" => Ok(NoisePattern::Ik),
+            _ => bail!("Unknown noise pattern {}, expected one of xx, ix or ik", s),
+        }
+    }
+}
+
+impl fmt::Display for NoisePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoisePattern::Xx => write!(f, "xx"),
+            NoisePattern::Ix => write!(f, "ix"),
+            NoisePattern::Ik => write!(f, "ik"),
+        }
+    }
+}
+
+/// Protocols that can be composed together with the rendezvous behaviour
+#[derive(Debug, Clone, Copy)]
+pub struct Protocols {
+    pub ping: Option<PingSettings>,
+    /// Interval at which pings are sent to keep connections alive while they
+    /// are idle. Takes precedence over `ping`.
+    pub keep_alive: Option<Duration>,
+    pub identify: bool,
+    /// Answer Kademlia DHT queries to serve as a bootstrap node
+    pub kademlia: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PingSettings {
+    pub interval: Duration,
+    pub keep_alive: bool,
+}
+
+#[derive(libp2p::NetworkBehaviour)]
+#[behaviour(event_process = false)]
+#[behaviour(out_event = "Event")]
+pub struct Behaviour {
+    ping: Toggle<Ping>,
+    identify: Toggle<Identify>,
+    kademlia: Toggle<Kademlia<MemoryStore>>,
+    rendezvous: FilteredRendezvous,
+}
+
+impl Behaviour {
+    fn new(
+        rendezvous: FilteredRendezvous,
+        protocols: Protocols,
+        local_public_key: identity::PublicKey,
+    ) -> Self {
+        let ping_config = match (protocols.keep_alive, protocols.ping) {
+            (Some(interval), _) => Some(
+                PingConfig::new()
+                    .with_keep_alive(true)
+                    .with_interval(interval),
+            ),
+            (None, Some(settings)) => Some(
+                PingConfig::new()
+                    .with_keep_alive(settings.keep_alive)
+                    .with_interval(settings.interval),
+            ),
+            (None, None) => None,
+        };
+        let ping = Toggle::from(ping_config.map(Ping::new));
+        let local_peer_id = local_public_key.clone().into_peer_id();
+        let identify = Toggle::from(protocols.identify.then(|| {
+            Identify::new(
+                IdentifyConfig::new(PROTOCOL_VERSION.to_owned(), local_public_key)
+                    .with_agent_version(format!("rendezvous-server/{}", env!("CARGO_PKG_VERSION"))),
+            )
+        }));
+        let kademlia = Toggle::from(protocols.kademlia.then(|| {
+            Kademlia::with_config(
+                local_peer_id,
+                MemoryStore::new(local_peer_id),
+                KademliaConfig::default(),
+            )
+        }));
+
+        Self {
+            // TODO: Remove Ping behaviour once https://github.com/libp2p/rust-libp2p/issues/2109 is fixed
+            // interval for sending Ping defaults to 24 hours
+            ping,
+            identify,
+            kademlia,
+            rendezvous,
+        }
+    }
+}
+
+struct Addresses<'a>(&'a [Multiaddr]);
+
+// Prints an array of multiaddresses as a comma seperated string
+impl fmt::Display for Addresses<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let display = self
+            .0
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        write!(f, "{}", display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_listen_address_of() {
+        let cases = [
+            ("/ip4/0.0.0.0/tcp/0", "/ip4/10.0.0.5/tcp/4001", true),
+            ("/ip4/0.0.0.0/tcp/4001", "/ip4/10.0.0.5/tcp/4001", true),
+            ("/ip4/10.0.0.5/tcp/0", "/ip4/10.0.0.5/tcp/4001", true),
+            ("/ip4/10.0.0.5/tcp/4001", "/ip4/10.0.0.5/tcp/4001", true),
+            ("/ip6/::/tcp/0/ws", "/ip6/::1/tcp/4001/ws", true),
+            ("/memory/0", "/memory/42", true),
+            ("/ip4/0.0.0.0/tcp/4001", "/ip4/10.0.0.5/tcp/4002", false),
+            ("/ip4/10.0.0.5/tcp/0", "/ip4/10.0.0.6/tcp/4001", false),
+            ("/ip4/0.0.0.0/tcp/0", "/ip6/::1/tcp/4001", false),
+            ("/ip6/::/tcp/0", "/ip4/10.0.0.5/tcp/4001", false),
+            ("/ip4/0.0.0.0/tcp/0", "/ip4/10.0.0.5/tcp/4001/ws", false),
+            ("/ip4/0.0.0.0/tcp/0/ws", "/ip4/10.0.0.5/tcp/4001", false),
+            ("/ip4/0.0.0.0/tcp/0/ws", "/ip4/10.0.0.5/tcp/4001/wss", false),
+            ("/memory/1", "/memory/2", false),
+        ];
+
+        for (requested, reported, expected) in cases.iter() {
+            let requested = requested.parse::<Multiaddr>().unwrap();
+            let reported = reported.parse::<Multiaddr>().unwrap();
+
+            assert_eq!(
+                super::is_listen_address_of(&requested, &reported),
+                *expected,
+                "{} {}",
+                requested,
+                reported
+            );
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::fmt::MakeWriter;
+
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 100 * 1024 * 1024;
+pub const DEFAULT_LOG_MAX_FILES: usize = 5;
+
+/// Log file that is rotated once it would exceed `max_size` bytes.
+///
+/// Rotated files are named `<path>.1` for the most recent one up to
+/// `<path>.<max_files>`, older files are deleted.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+
+            return Ok(());
+        }
+
+        let _ = fs::remove_file(rotated_path(&self.path, self.max_files));
+        for index in (1..self.max_files).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Destination of the logs, passed to the subscriber as writer.
+#[derive(Clone)]
+pub enum LogWriter {
+    Stderr,
+    File {
+        file: Arc<Mutex<RotatingFile>>,
+        also_stderr: bool,
+    },
+}
+
+impl LogWriter {
+    pub fn file(file: RotatingFile, also_stderr: bool) -> Self {
+        LogWriter::File {
+            file: Arc::new(Mutex::new(file)),
+            also_stderr,
+        }
+    }
+}
+
+impl MakeWriter for LogWriter {
+    type Writer = LogWriter;
+
+    fn make_writer(&self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+// the subscriber writes each event with a single call, locking the file per
+// call keeps events of concurrent threads apart
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LogWriter::Stderr => io::stderr().write(buf),
+            LogWriter::File { file, also_stderr } => {
+                if *also_stderr {
+                    io::stderr().write_all(buf)?;
+                }
+                file.lock()
+                    .expect("log file is not written after a panic")
+                    .write_all(buf)?;
+
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogWriter::Stderr => io::stderr().flush(),
+            LogWriter::File { file, .. } => file
+                .lock()
+                .expect("log file is not written after a panic")
+                .flush(),
+        }
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+
+    PathBuf::from(rotated)
+}
@@ -1,202 +1,961 @@
-use anyhow::{bail, Context, Result};
-use futures::{AsyncRead, AsyncWrite, StreamExt};
-use libp2p::core::muxing::StreamMuxerBox;
-use libp2p::core::transport::Boxed;
-use libp2p::core::upgrade::{SelectUpgrade, Version};
-use libp2p::dns::TokioDnsConfig;
+mod config;
+
+use crate::config::ConfigFile;
+use anyhow::{anyhow, bail, Context, Result};
+use ipnet::IpNet;
 use libp2p::identity::ed25519;
-use libp2p::mplex::MplexConfig;
-use libp2p::noise::{NoiseConfig, X25519Spec};
-use libp2p::ping::{Ping, PingConfig, PingEvent};
-use libp2p::rendezvous::{Config, Event as RendezvousEvent, Rendezvous};
-use libp2p::swarm::toggle::Toggle;
-use libp2p::swarm::{SwarmBuilder, SwarmEvent};
-use libp2p::tcp::TokioTcpConfig;
-use libp2p::websocket::tls::{Certificate, PrivateKey};
-use libp2p::websocket::{tls, WsConfig};
-use libp2p::yamux::YamuxConfig;
-use libp2p::{identity, noise, rendezvous, Multiaddr, PeerId, Swarm, Transport};
-use std::fmt;
+use libp2p::multiaddr::Protocol;
+use libp2p::{identity, Multiaddr, PeerId};
+#[cfg(unix)]
+use rendezvous_server::request_registrations;
+use rendezvous_server::{
+    LogWriter, Muxer, NoisePattern, PingSettings, Policy, Protocols, RendezvousServer,
+    RotatingFile, ServerConfig, TcpSettings, YamuxSettings, DEFAULT_LOG_MAX_FILES,
+    DEFAULT_LOG_MAX_SIZE, DEFAULT_MAX_ADDRESSES_PER_REGISTRATION, DEFAULT_PING_INTERVAL_SECS,
+};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
+use std::{fmt, io};
 use structopt::StructOpt;
 use tokio::fs;
 use tokio::fs::{DirBuilder, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::fmt::time::ChronoLocal;
-use tracing_subscriber::FmtSubscriber;
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+
+/// Printed by `--version`, `-V` only prints the crate version
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\nlibp2p ",
+    env!("LIBP2P_VERSION"),
+    "\ncommit ",
+    env!("GIT_COMMIT"),
+    " (",
+    env!("GIT_COMMIT_DATE"),
+    ")"
+);
 
 #[derive(Debug, StructOpt)]
+#[structopt(long_version = LONG_VERSION)]
 struct Cli {
+    /// Path to a TOML config file. Keys are named like the flags in snake
+    /// case, e.g. listen_tcp = 8888. Repeatable flags are lists named
+    /// listen_addresses, external_addresses, tls_private_keys,
+    /// tls_certificates, allowed_namespaces, allowed_peers, denied_peers,
+    /// denied_cidrs and upstream_namespaces. Flags provided on the command line
+    /// take precedence over the config file, except for switches without a
+    /// value, e.g. --quiet: a switch set to true in the config file cannot
+    /// be turned off on the command line.
+    #[structopt(long)]
+    config: Option<PathBuf>,
+
     /// Path to the file that contains the secret key of the rendezvous server's
     /// identity keypair
     #[structopt(long)]
-    secret_file: PathBuf,
+    secret_file: Option<PathBuf>,
+    /// Name of the environment variable that contains the hex or base64
+    /// encoded secret key of the rendezvous server's identity keypair. Cannot
+    /// be combined with --secret-file.
+    #[structopt(long)]
+    secret_env: Option<String>,
     /// Set this flag to generate a secret file at the path specified by the
     /// --secret-file argument
     #[structopt(long)]
     generate_secret: bool,
+    /// Overwrite an existing secret file with --generate-secret. This changes
+    /// the peer id of the server, invalidating all references to it.
+    #[structopt(long)]
+    force: bool,
+    /// Encoding of the secret key in --secret-file or --secret-env, one of
+    /// raw, hex or base64. By default, a file of exactly 32 bytes is read as
+    /// raw key and anything else as hex or base64.
+    #[structopt(long)]
+    secret_format: Option<SecretFormat>,
+    /// Print the secret key generated by --generate-secret base64 encoded to
+    /// stdout and exit instead of writing it to --secret-file. The key can be
+    /// loaded with --secret-env.
+    #[structopt(long)]
+    secret_stdout: bool,
+    /// Print the peer id of the rendezvous server's identity to stdout and
+    /// exit without starting the server
+    #[structopt(long)]
+    print_peer_id: bool,
+    /// Validate the configuration by loading the secret key and TLS files and
+    /// setting up the transport and swarm, then exit without listening
+    #[structopt(long)]
+    check: bool,
+    /// Print the effective configuration as JSON to stdout and exit without
+    /// starting the server. The secret key is never printed.
+    #[structopt(long)]
+    print_config: bool,
 
-    /// Port used for listening on TCP (default)
+    /// Port used for listening on TCP
+    #[structopt(long)]
+    listen_tcp: Option<u16>,
+    /// Name of the environment variable that contains the port used for
+    /// listening on TCP, e.g. PORT. Cannot be combined with --listen-tcp.
+    #[structopt(long)]
+    listen_tcp_env: Option<String>,
+    /// Multiaddress to listen on, e.g. /ip4/10.0.0.5/tcp/6000 or
+    /// /ip4/10.0.0.5/tcp/8889/wss. Can be provided multiple times.
+    #[structopt(long = "listen-addr", parse(try_from_str = parse_multiaddr))]
+    listen_addresses: Vec<Multiaddr>,
+    /// Port used for listening on the in-memory transport, i.e. /memory/<n>.
+    /// Only reachable from within the process, mostly useful for tests.
+    /// Cannot be combined with --listen-tcp, --listen-websocket or
+    /// --listen-addr.
+    #[structopt(long)]
+    listen_memory: Option<u64>,
+    /// Externally reachable multiaddress announced to peers, e.g. if the
+    /// server runs behind NAT. Can be provided multiple times.
+    #[structopt(long = "external-address", parse(try_from_str = parse_multiaddr))]
+    external_addresses: Vec<Multiaddr>,
+    /// Announce the address most connected peers observed the server on as
+    /// external address once peers from at least two IPs reported it. Only
+    /// addresses on a port the server listens on are considered. Requires
+    /// --identify, which is how peers report the observed address.
+    #[structopt(long)]
+    auto_external_address: bool,
+    /// Multiaddress of another rendezvous server to register this server
+    /// with, e.g. /dns4/rendezvous.example.com/tcp/8888/p2p/<peer id>.
+    /// Registrations are refreshed before their TTL expires. Requires an
+    /// external address.
+    #[structopt(long, parse(try_from_str = parse_multiaddr))]
+    upstream: Option<Multiaddr>,
+    /// Namespace to register in with --upstream. Can be provided multiple
+    /// times or as comma separated list.
+    #[structopt(
+        long = "upstream-namespace",
+        use_delimiter = true,
+        parse(try_from_str = parse_namespace)
+    )]
+    upstream_namespaces: Vec<String>,
+    /// Additionally listen on IPv6 for the ports provided by --listen-tcp and
+    /// --listen-websocket. Failing to listen on IPv6 is not fatal.
+    #[structopt(long)]
+    listen_ip6: bool,
+    /// Number of times listening on an address is retried before giving up,
+    /// e.g. while the port is still in use by a previous instance. Defaults
+    /// to 5.
+    #[structopt(long)]
+    listen_retries: Option<u32>,
+    /// Delay in seconds between attempts to listen on an address. Defaults to
+    /// 1.
     #[structopt(long)]
-    listen_tcp: u16,
+    listen_retry_delay: Option<u64>,
+    /// Delay in seconds before binding the listeners, e.g. to let dependent
+    /// services start first. The health endpoint reports not ready until
+    /// the listeners are bound. Listeners are bound immediately if not
+    /// provided or 0.
+    #[structopt(long)]
+    startup_delay: Option<u64>,
+    /// Do not resolve DNS multiaddresses. Useful if only IP addresses are
+    /// used and DNS resolution is slow or unavailable.
+    #[structopt(long)]
+    no_dns: bool,
     /// Format logs as JSON
     #[structopt(long)]
     json: bool,
+    /// Log level, one of trace, debug, info, warn, error or off. Defaults to
+    /// info. Ignored if the RUST_LOG environment variable is set, which takes
+    /// filter directives like rendezvous_server=debug.
+    #[structopt(long)]
+    log_level: Option<LevelFilter>,
+    /// Log level of the libp2p crates, e.g. debug to see the logs of
+    /// libp2p_swarm and libp2p_noise. libp2p logs are not shown if not
+    /// provided. Applies in addition to RUST_LOG.
+    #[structopt(long)]
+    log_libp2p: Option<LevelFilter>,
 
     /// Don't include timestamp in logs. Useful if captured logs already get
     /// timestamped, e.g. through journald.
     #[structopt(long)]
     no_timestamp: bool,
+    /// Write logs to this file instead of stderr. The file is rotated once it
+    /// exceeds --log-max-size.
+    #[structopt(long)]
+    log_file: Option<PathBuf>,
+    /// Size in bytes at which the log file is rotated. Defaults to 104857600,
+    /// i.e. 100 MiB.
+    #[structopt(long)]
+    log_max_size: Option<u64>,
+    /// Number of rotated log files to keep. Defaults to 5.
+    #[structopt(long)]
+    log_max_files: Option<usize>,
+    /// Write logs to stderr in addition to --log-file
+    #[structopt(long)]
+    log_also_stderr: bool,
+    /// Don't log served discover requests and connection events at info
+    /// level. Registrations, failed registrations, expiries, warnings and
+    /// errors are still logged. Applies in addition to --log-level.
+    #[structopt(long)]
+    quiet: bool,
+    /// Window in seconds within which refreshes of the same registration are
+    /// logged only once. The number of suppressed log lines is logged once
+    /// per window. New registrations are always logged.
+    #[structopt(long)]
+    registration_log_throttle: Option<u64>,
 
     /// Compose the ping behaviour together with the rendezvous behaviour in
     /// case a rendezvous server with Ping is required. This feature will be removed once https://github.com/libp2p/rust-libp2p/issues/2109 is fixed.
     #[structopt(long)]
     ping: bool,
-    /// Port used for listening on websocket
+    /// Interval in seconds at which pings are sent if --ping is set. Defaults
+    /// to 86400, i.e. 24 hours.
+    #[structopt(long)]
+    ping_interval: Option<u64>,
+    /// Keep connections alive through pings if --ping is set
+    #[structopt(long)]
+    ping_keepalive: bool,
+    /// Keep idle connections open by sending pings at this interval in
+    /// seconds, e.g. for peers that stay connected for the TTL of their
+    /// registration. Independent of --ping and takes precedence over its
+    /// settings.
+    #[structopt(long)]
+    keep_alive: Option<u64>,
+    /// Compose the identify behaviour together with the rendezvous behaviour
+    /// to advertise the server's protocols and listen addresses and report
+    /// observed addresses to clients
+    #[structopt(long)]
+    identify: bool,
+    /// Compose the Kademlia behaviour together with the rendezvous behaviour
+    /// to answer DHT queries and serve as a bootstrap node. Combine with
+    /// --identify to add inbound peers to the routing table.
+    #[structopt(long)]
+    kademlia: bool,
+    /// Port used for listening on websocket on all interfaces. Use
+    /// --listen-addr to bind to a specific IP, e.g.
+    /// /ip4/10.0.0.5/tcp/8889/ws.
     #[structopt(long)]
     listen_websocket: Option<u16>,
+    /// Name of the environment variable that contains the port used for
+    /// listening on websocket. Cannot be combined with --listen-websocket.
+    #[structopt(long)]
+    listen_websocket_env: Option<String>,
 
     /// Path to server private key for secure websocket connection
-    /// configuration.
-    #[structopt(long)]
-    tls_private_key: Option<PathBuf>,
+    /// configuration. Can be provided multiple times, once per certificate.
+    #[structopt(long = "tls-private-key")]
+    tls_private_keys: Vec<PathBuf>,
     /// Path to server SSL certificate for secure websocket connection
-    /// configuration.
+    /// configuration. Can be provided multiple times, but only the first
+    /// certificate is served, the others are only validated.
+    #[structopt(long = "tls-certificate")]
+    tls_certificates: Vec<PathBuf>,
+    /// Path to a PEM file containing both the server private key and the
+    /// certificate chain for secure websocket connections.
+    /// Cannot be combined with --tls-private-key or --tls-certificate.
+    #[structopt(long)]
+    tls_bundle: Option<PathBuf>,
+    /// Start even if the TLS certificate is expired. Websocket clients reject
+    /// the certificate until it is replaced.
+    #[structopt(long)]
+    allow_expired_cert: bool,
+    /// Log a warning if the TLS certificate expires within this number of
+    /// days. Defaults to 14.
+    #[structopt(long)]
+    cert_expiry_warning_days: Option<u64>,
+
+    /// Minimum TTL in seconds that peers can request for their registrations
+    #[structopt(long)]
+    min_ttl: Option<u64>,
+    /// Maximum TTL in seconds that peers can request for their registrations
+    #[structopt(long)]
+    max_ttl: Option<u64>,
+    /// Namespace peers are allowed to register in. Can be provided multiple
+    /// times or as comma separated list, e.g. "foo, bar". All namespaces are
+    /// allowed if not provided. Registrations in other namespaces are not
+    /// reported but stay discoverable until their TTL expires.
+    #[structopt(
+        long = "allow-namespace",
+        use_delimiter = true,
+        parse(try_from_str = parse_namespace)
+    )]
+    allowed_namespaces: Vec<String>,
+    /// Peer that is allowed to register. Can be provided multiple times. If
+    /// provided, only these peers can register. Takes precedence over
+    /// --deny-peer. Requests of other peers are dropped and connections to
+    /// them are closed, so they cannot discover either.
+    #[structopt(long = "allow-peer")]
+    allowed_peers: Vec<PeerId>,
+    /// Path to a file with one peer id per line that are allowed to register,
+    /// in addition to --allow-peer. If provided, only these peers can
+    /// register. The file is re-read on SIGHUP and when it changes, invalid
+    /// lines are skipped.
+    #[structopt(long)]
+    allow_peer_file: Option<PathBuf>,
+    /// Peer that is not allowed to register. Can be provided multiple times.
+    /// Requests of denied peers are dropped and connections to them are
+    /// closed, so they cannot discover either.
+    #[structopt(long = "deny-peer")]
+    denied_peers: Vec<PeerId>,
+    /// Maximum number of namespaces a peer can be registered in at the same
+    /// time. Registrations exceeding the cap are not reported but stay
+    /// discoverable until their TTL expires. Not limited if not provided.
+    #[structopt(long)]
+    max_namespaces_per_peer: Option<usize>,
+    /// Keep the connections to peers whose registration exceeds
+    /// --max-namespaces-per-peer open. By default, they are closed so the peer
+    /// cannot send further requests on them
+    #[structopt(long)]
+    keep_connection_over_namespace_cap: bool,
+    /// Maximum number of registrations across all peers. Once reached, requests
+    /// of peers without a registration are dropped before their registrations
+    /// are stored and connections to them are closed. New registrations of
+    /// registered peers are not reported but stay discoverable until their TTL
+    /// expires. Not limited if not provided.
+    #[structopt(long)]
+    max_total_registrations: Option<usize>,
+    /// Stop reporting the oldest registration to make room for a new one once
+    /// --max-total-registrations is reached, instead of rejecting the new one.
+    /// The evicted registration stays discoverable until its TTL expires, so
+    /// the cap no longer bounds the stored registrations
+    #[structopt(long)]
+    evict_on_full: bool,
+    /// Reject registrations with a private, loopback or otherwise unroutable
+    /// IP address, which discoverers on the internet cannot reach. Rejected
+    /// registrations are not reported but stay discoverable until their TTL
+    /// expires.
+    #[structopt(long)]
+    reject_private_addresses: bool,
+    /// Only reject registrations without any routable IP address. The
+    /// unroutable addresses of accepted registrations are part of the signed
+    /// peer record and still returned to discoverers.
+    #[structopt(long)]
+    allow_partially_routable: bool,
+    /// Maximum number of addresses in a registration. Registrations with more
+    /// addresses are rejected, they are not reported but stay discoverable
+    /// until their TTL expires. Defaults to 16.
+    #[structopt(long)]
+    max_addresses_per_registration: Option<usize>,
+    /// Only accept registrations of peers that completed identify. Requires
+    /// --identify. Connections to other registering peers are closed.
+    #[structopt(long)]
+    require_identify: bool,
+    /// Only accept registrations of peers whose identify agent version starts
+    /// with this prefix, e.g. "xmr-btc-swap/". Implies --require-identify.
+    #[structopt(long)]
+    require_agent_prefix: Option<String>,
+
+    /// Reject incoming connections from an IP range in CIDR notation, e.g.
+    /// 10.0.0.0/8, before the security handshake. Can be provided multiple
+    /// times.
+    #[structopt(long = "deny-cidr")]
+    denied_cidrs: Vec<IpNet>,
+    /// Maximum number of discover requests a peer can send per minute. Peers
+    /// exceeding the limit are disconnected and banned until their rate drops
+    /// below the limit. Not limited if not provided.
+    #[structopt(long)]
+    max_discover_per_minute: Option<u32>,
+    /// Maximum number of connections a peer can establish per minute. A
+    /// warning is logged for peers exceeding the limit, which are banned if
+    /// --ban-duration is provided. Not limited if not provided.
+    #[structopt(long)]
+    max_reconnects_per_minute: Option<u32>,
+    /// Time in seconds peers exceeding --max-reconnects-per-minute are banned
+    /// for. Peers are only logged if not provided.
+    #[structopt(long)]
+    ban_duration: Option<u64>,
+
+    /// Timeout in seconds for authenticating and multiplexing new
+    /// connections. Defaults to 20.
+    #[structopt(long)]
+    handshake_timeout: Option<u64>,
+    /// Timeout in seconds for outgoing dials, e.g. to the upstream rendezvous
+    /// server or for the self-test, including the handshake. Outgoing dials
+    /// are only bounded by --handshake-timeout if not provided.
+    #[structopt(long)]
+    dial_timeout: Option<u64>,
+
+    /// Stream multiplexers offered to peers, one of yamux, mplex or both.
+    /// Defaults to both.
+    #[structopt(long)]
+    muxer: Option<Muxer>,
+    /// Noise handshake pattern used to authenticate connections, one of xx or
+    /// ix. Clients have to use the same pattern. ik is rejected because it
+    /// requires the static key of the remote peer. Defaults to xx.
+    #[structopt(long)]
+    noise_pattern: Option<NoisePattern>,
+    /// Maximum number of bytes buffered per yamux stream
+    #[structopt(long)]
+    yamux_max_buffer_size: Option<usize>,
+    /// Size of the yamux receive window per stream in bytes, at least 262144
+    #[structopt(long)]
+    yamux_receive_window: Option<u32>,
+    /// Size of the accept backlog of TCP listeners. Defaults to 1024.
+    #[structopt(long)]
+    tcp_backlog: Option<u32>,
+    /// Disable Nagle's algorithm on TCP connections, true or false. Defaults
+    /// to true.
+    #[structopt(long)]
+    tcp_nodelay: Option<bool>,
+    /// Set SO_REUSEADDR and SO_REUSEPORT on TCP sockets so multiple server
+    /// instances can listen on the same port. SO_REUSEPORT is only set on
+    /// Unix, other platforms only reuse addresses.
+    #[structopt(long)]
+    tcp_reuse_port: bool,
+
+    /// Maximum number of concurrently established incoming connections.
+    /// Outgoing connections are not limited. Defaults to 4096.
+    #[structopt(long)]
+    max_established_connections: Option<u32>,
+    /// Maximum number of concurrently pending incoming connections, i.e.
+    /// connections that are still negotiating. Defaults to 512.
     #[structopt(long)]
-    tls_certificate: Option<PathBuf>,
+    max_pending_connections: Option<u32>,
+    /// Maximum number of concurrently pending outgoing connections. Not
+    /// limited if not provided.
+    #[structopt(long)]
+    max_concurrent_dials: Option<u32>,
+
+    /// Socket address to serve Prometheus metrics on, e.g. 127.0.0.1:9100.
+    /// Metrics are not served if not provided.
+    #[structopt(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// Maximum number of namespaces registration metrics are labeled with.
+    /// Further namespaces are counted as namespace="other". Defaults to 100.
+    #[structopt(long)]
+    metrics_max_namespaces: Option<usize>,
+    /// Socket address to serve the /health readiness probe on, e.g.
+    /// 127.0.0.1:8080. The probe is not served if not provided.
+    #[structopt(long)]
+    health_addr: Option<SocketAddr>,
+    /// Socket address to serve the admin API on, e.g. 127.0.0.1:8081. Lists
+    /// the active registrations on /registrations and the number of
+    /// registrations per namespace on /namespaces. Requires --admin-token.
+    #[structopt(long)]
+    admin_addr: Option<SocketAddr>,
+    /// Bearer token that has to be provided with every request to the admin
+    /// API and the gRPC API
+    #[structopt(long)]
+    admin_token: Option<String>,
+    /// Disconnect peers whose oldest connection is older than this many
+    /// seconds, making them dial again, e.g. to rebalance connections across
+    /// server instances. Connections are not recycled if not provided.
+    #[structopt(long)]
+    max_connection_age: Option<u64>,
+    /// Don't recycle the connections of peers with an active registration.
+    /// Requires --max-connection-age.
+    #[structopt(long)]
+    exempt_registered: bool,
+    /// Address of a standby server to stream registration events to, e.g.
+    /// 10.0.0.6:9000. The connection is re-established when it is lost, the
+    /// standby may be stale after a gap. Requires --mirror-secret.
+    #[structopt(long)]
+    mirror_to: Option<SocketAddr>,
+    /// Socket address to receive the registration events of a primary server
+    /// on and apply them to the tracked registrations, e.g. 10.0.0.6:9000.
+    /// Requires --mirror-secret.
+    #[structopt(long)]
+    mirror_from: Option<SocketAddr>,
+    /// Shared secret the primary sends before streaming events, has to be the
+    /// same on the primary and the standby. Required by --mirror-to and
+    /// --mirror-from.
+    #[structopt(long)]
+    mirror_secret: Option<String>,
+    /// Socket address to serve the gRPC API on, e.g. 127.0.0.1:8082. Lists
+    /// registrations and streams events, see proto/rendezvous.proto. Requests
+    /// have to carry --admin-token as bearer token. Requires the server to be
+    /// built with the grpc feature.
+    #[structopt(long)]
+    grpc_addr: Option<SocketAddr>,
+
+    /// Dial the first listen address once it is reported to verify that the
+    /// transport, noise and multiplexer setup works. The result is logged.
+    #[structopt(long)]
+    self_test: bool,
+    /// Exit with an error if the self-test fails. Implies --self-test.
+    #[structopt(long)]
+    self_test_fatal: bool,
+
+    /// Path to a file the active registrations are periodically persisted to
+    /// and restored from on startup
+    #[structopt(long)]
+    state_file: Option<PathBuf>,
+
+    /// Interval in seconds at which a summary of the active registrations
+    /// per namespace is logged. Set to 0 to disable the summary. Defaults to
+    /// 60.
+    #[structopt(long)]
+    summary_interval: Option<u64>,
+    /// Number of handled events buffered for logging, metrics and event
+    /// notifications. If the buffer is full, notifications to --event-socket
+    /// and served discover requests are dropped until the backlog is
+    /// processed. Defaults to 1024.
+    #[structopt(long)]
+    event_buffer: Option<usize>,
+    /// Number of recent rendezvous events kept in memory and served on GET
+    /// /audit by the admin API. Set to 0 to disable. Defaults to 1000.
+    #[structopt(long)]
+    audit_buffer_size: Option<usize>,
+
+    /// Path of a Unix domain socket to stream rendezvous events to as JSON
+    /// lines
+    #[cfg(unix)]
+    #[structopt(long)]
+    event_socket: Option<PathBuf>,
+    /// Path of a Unix domain socket to answer requests of other instances on,
+    /// e.g. of --registrations-snapshot. Only the owner can connect. A stale
+    /// socket at the path is replaced, any other file is left alone.
+    #[cfg(unix)]
+    #[structopt(long)]
+    control_socket: Option<PathBuf>,
+    /// Print the active registrations of the server running with
+    /// --control-socket as JSON to stdout and exit
+    #[cfg(unix)]
+    #[structopt(long)]
+    registrations_snapshot: bool,
+    /// Append registrations, unregistrations and expiries as JSON lines to
+    /// this file, independent of --log-file
+    #[structopt(long)]
+    registration_log: Option<PathBuf>,
+    /// Size in bytes at which the registration log is rotated. Defaults to
+    /// 104857600, i.e. 100 MiB.
+    #[structopt(long)]
+    registration_log_max_size: Option<u64>,
+}
+
+/// Length of an ed25519 secret key in bytes
+const SECRET_KEY_LENGTH: usize = 32;
+
+/// Encoding of a secret key loaded from a file or environment variable
+#[derive(Debug, Clone, Copy)]
+enum SecretFormat {
+    Raw,
+    Hex,
+    Base64,
+}
+
+impl FromStr for SecretFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raw" => Ok(SecretFormat::Raw),
+            "hex" => Ok(SecretFormat::Hex),
+            "base64" => Ok(SecretFormat::Base64),
+            _ => bail!(
+                "Unknown secret format {}, expected one of raw, hex or base64",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SecretFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretFormat::Raw => write!(f, "raw"),
+            SecretFormat::Hex => write!(f, "hex"),
+            SecretFormat::Base64 => write!(f, "base64"),
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::from_args();
+    let mut cli = Cli::from_args();
+    if let Some(config) = &cli.config {
+        ConfigFile::load(config)?.merge_into(&mut cli)?;
+    }
 
-    init_tracing(LevelFilter::INFO, cli.json, cli.no_timestamp);
+    let log_writer = match &cli.log_file {
+        Some(path) => {
+            let max_size = match cli.log_max_size {
+                Some(0) => bail!("--log-max-size has to be greater than 0"),
+                max_size => max_size.unwrap_or(DEFAULT_LOG_MAX_SIZE),
+            };
+            let file = RotatingFile::open(
+                path.clone(),
+                max_size,
+                cli.log_max_files.unwrap_or(DEFAULT_LOG_MAX_FILES),
+            )
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+
+            LogWriter::file(file, cli.log_also_stderr)
+        }
+        None => LogWriter::Stderr,
+    };
+
+    init_tracing(
+        cli.log_level.unwrap_or(LevelFilter::INFO),
+        cli.log_libp2p,
+        cli.json,
+        cli.no_timestamp,
+        log_writer,
+    );
+
+    #[cfg(unix)]
+    if cli.registrations_snapshot {
+        match &cli.control_socket {
+            Some(path) => println!("{}", request_registrations(path).await?),
+            None => bail!("--registrations-snapshot requires --control-socket"),
+        }
+        return Ok(());
+    }
 
-    let secret_key = match cli.generate_secret {
-        true => {
+    if cli.check && cli.generate_secret {
+        bail!("--generate-secret cannot be combined with --check");
+    }
+
+    if cli.force && !cli.generate_secret {
+        bail!("--force can only be used with --generate-secret");
+    }
+
+    if cli.secret_stdout {
+        if !cli.generate_secret {
+            bail!("--secret-stdout can only be used with --generate-secret");
+        }
+        if cli.secret_file.is_some() || cli.secret_env.is_some() {
+            bail!("--secret-stdout cannot be combined with --secret-file or --secret-env");
+        }
+
+        // logs are written to stderr, so stdout only contains the key
+        println!("{}", base64::encode(ed25519::SecretKey::generate()));
+        return Ok(());
+    }
+
+    let secret_key = match (cli.secret_file, cli.secret_env, cli.generate_secret) {
+        (Some(secret_file), None, true) => {
             let secret_key = ed25519::SecretKey::generate();
-            write_secret_key_to_file(&secret_key, cli.secret_file).await?;
+            write_secret_key_to_file(&secret_key, secret_file, cli.force).await?;
 
             secret_key
         }
-        false => load_secret_key_from_file(&cli.secret_file).await?,
+        (Some(secret_file), None, false) => {
+            load_secret_key_from_file(&secret_file, cli.secret_format).await?
+        }
+        (None, Some(secret_env), false) => {
+            load_secret_key_from_env(&secret_env, cli.secret_format)?
+        }
+        (None, Some(_), true) => {
+            bail!("--generate-secret can only be used with --secret-file or --secret-stdout")
+        }
+        (Some(_), Some(_), _) => bail!("Only one of --secret-file or --secret-env can be provided"),
+        (None, None, _) => bail!("Either --secret-file or --secret-env has to be provided"),
     };
     let identity = identity::Keypair::Ed25519(secret_key.into());
 
-    let tls_config = tls_config_from_params(
-        cli.tls_private_key,
-        cli.tls_certificate,
-        cli.listen_websocket.is_some(),
-    )
-    .await?;
+    if cli.print_peer_id {
+        println!("{}", identity.public().into_peer_id());
+        return Ok(());
+    }
+
+    let mut config = ServerConfig::new(identity);
+
+    config.listen_tcp = match (cli.listen_tcp, cli.listen_tcp_env) {
+        (Some(_), Some(_)) => bail!("--listen-tcp cannot be combined with --listen-tcp-env"),
+        (None, Some(var)) => Some(load_port_from_env(&var)?),
+        (port, None) => port,
+    };
+    config.listen_websocket = match (cli.listen_websocket, cli.listen_websocket_env) {
+        (Some(_), Some(_)) => {
+            bail!("--listen-websocket cannot be combined with --listen-websocket-env")
+        }
+        (None, Some(var)) => Some(load_port_from_env(&var)?),
+        (port, None) => port,
+    };
+    for address in &cli.listen_addresses {
+        validate_listen_address(address)?;
+    }
+    config.listen_addresses = cli.listen_addresses;
+    config.listen_memory = cli.listen_memory;
+    config.listen_ip6 = cli.listen_ip6;
+    if let Some(listen_retries) = cli.listen_retries {
+        config.listen_retries = listen_retries;
+    }
+    if let Some(secs) = cli.listen_retry_delay {
+        config.listen_retry_delay = Duration::from_secs(secs);
+    }
+    config.startup_delay = cli
+        .startup_delay
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs);
+    config.dns = !cli.no_dns;
+    config.external_addresses = cli.external_addresses;
+    config.auto_external_address = cli.auto_external_address;
+    config.upstream = cli.upstream;
+    config.upstream_namespaces = cli.upstream_namespaces;
+    match cli.tls_bundle {
+        Some(_) if !cli.tls_private_keys.is_empty() || !cli.tls_certificates.is_empty() => {
+            bail!("--tls-bundle cannot be combined with --tls-private-key or --tls-certificate")
+        }
+        // the key and the certificates are read from the same file
+        Some(bundle) => {
+            config.tls_private_keys = vec![bundle.clone()];
+            config.tls_certificates = vec![bundle];
+        }
+        None => {
+            config.tls_private_keys = cli.tls_private_keys;
+            config.tls_certificates = cli.tls_certificates;
+        }
+    }
+    config.certificate_expiry.allow_expired = cli.allow_expired_cert;
+    if let Some(days) = cli.cert_expiry_warning_days {
+        config.certificate_expiry.warning_threshold = Duration::from_secs(days * 86_400);
+    }
+    config.min_ttl = cli.min_ttl;
+    config.max_ttl = cli.max_ttl;
+    config.policy = Policy {
+        allowed_namespaces: cli.allowed_namespaces.into_iter().collect(),
+        allowed_peers: cli.allowed_peers.into_iter().collect(),
+        allowed_peers_file: None,
+        require_identify: cli.require_identify,
+        required_agent_prefix: cli.require_agent_prefix,
+        identified_peers: Default::default(),
+        denied_peers: cli.denied_peers.into_iter().collect(),
+        max_namespaces_per_peer: cli.max_namespaces_per_peer,
+        keep_connection_over_namespace_cap: cli.keep_connection_over_namespace_cap,
+        max_total_registrations: cli.max_total_registrations,
+        evict_on_full: cli.evict_on_full,
+        reject_private_addresses: cli.reject_private_addresses,
+        allow_partially_routable: cli.allow_partially_routable,
+        max_addresses_per_registration: match cli.max_addresses_per_registration {
+            Some(0) => bail!("--max-addresses-per-registration has to be greater than 0"),
+            max => Some(max.unwrap_or(DEFAULT_MAX_ADDRESSES_PER_REGISTRATION)),
+        },
+    };
+    config.allow_peer_file = cli.allow_peer_file;
+    config.denied_cidrs = cli.denied_cidrs;
+    config.max_discover_per_minute = match cli.max_discover_per_minute {
+        Some(0) => bail!("--max-discover-per-minute has to be greater than 0"),
+        limit => limit,
+    };
+    config.max_reconnects_per_minute = match cli.max_reconnects_per_minute {
+        Some(0) => bail!("--max-reconnects-per-minute has to be greater than 0"),
+        limit => limit,
+    };
+    if cli.ban_duration.is_some() && cli.max_reconnects_per_minute.is_none() {
+        tracing::warn!("--ban-duration has no effect without --max-reconnects-per-minute");
+    }
+    config.reconnect_ban_duration = cli.ban_duration.map(Duration::from_secs);
+
+    match cli.handshake_timeout {
+        Some(0) => bail!("--handshake-timeout has to be greater than 0"),
+        Some(secs) => config.handshake_timeout = Duration::from_secs(secs),
+        None => {}
+    }
+    config.dial_timeout = match cli.dial_timeout {
+        Some(0) => bail!("--dial-timeout has to be greater than 0"),
+        timeout => timeout.map(Duration::from_secs),
+    };
 
-    let ws_or_wss = if tls_config.is_some() { "wss" } else { "ws" };
+    if let Some(muxer) = cli.muxer {
+        config.muxer = muxer;
+    }
+    if let Some(noise_pattern) = cli.noise_pattern {
+        config.noise_pattern = noise_pattern;
+    }
+    config.yamux = YamuxSettings {
+        max_buffer_size: cli.yamux_max_buffer_size,
+        receive_window: cli.yamux_receive_window,
+    };
+    config.tcp_settings = TcpSettings {
+        backlog: cli.tcp_backlog,
+        nodelay: cli.tcp_nodelay,
+        reuse_port: cli.tcp_reuse_port,
+    };
 
-    let mut swarm = create_swarm(
-        identity,
-        cli.ping,
-        cli.listen_websocket.is_some(),
-        tls_config,
-    )?;
+    if !cli.ping && (cli.ping_interval.is_some() || cli.ping_keepalive) {
+        tracing::warn!("--ping-interval and --ping-keepalive have no effect without --ping");
+    }
+    config.protocols = Protocols {
+        ping: cli.ping.then(|| PingSettings {
+            interval: Duration::from_secs(cli.ping_interval.unwrap_or(DEFAULT_PING_INTERVAL_SECS)),
+            keep_alive: cli.ping_keepalive,
+        }),
+        keep_alive: match cli.keep_alive {
+            Some(0) => bail!("--keep-alive has to be greater than 0"),
+            keep_alive => keep_alive.map(Duration::from_secs),
+        },
+        identify: cli.identify,
+        kademlia: cli.kademlia,
+    };
 
-    tracing::info!(peer_id=%swarm.local_peer_id(), "Rendezvous server peer id");
+    if let Some(max_established_connections) = cli.max_established_connections {
+        config.max_established_connections = max_established_connections;
+    }
+    if let Some(max_pending_connections) = cli.max_pending_connections {
+        config.max_pending_connections = max_pending_connections;
+    }
+    config.max_concurrent_dials = match cli.max_concurrent_dials {
+        Some(0) => bail!("--max-concurrent-dials has to be greater than 0"),
+        limit => limit,
+    };
 
-    swarm
-        .listen_on(
-            format!("/ip4/0.0.0.0/tcp/{}", cli.listen_tcp)
-                .parse()
-                .expect("static string is valid MultiAddress"),
-        )
-        .context("Failed to initialize listener")?;
-
-    if let Some(websocket_port) = cli.listen_websocket {
-        swarm
-            .listen_on(
-                format!("/ip4/0.0.0.0/tcp/{}/{}", websocket_port, ws_or_wss)
-                    .parse()
-                    .unwrap(),
+    config.metrics_addr = cli.metrics_addr;
+    if let Some(metrics_max_namespaces) = cli.metrics_max_namespaces {
+        config.metrics_max_namespaces = metrics_max_namespaces;
+    }
+    config.health_addr = cli.health_addr;
+    config.admin_addr = cli.admin_addr;
+    config.admin_token = cli.admin_token;
+    config.grpc_addr = cli.grpc_addr;
+    config.mirror_to = cli.mirror_to;
+    config.mirror_from = cli.mirror_from;
+    config.mirror_secret = cli.mirror_secret;
+    config.max_connection_age = match cli.max_connection_age {
+        Some(0) => bail!("--max-connection-age has to be greater than 0"),
+        age => age.map(Duration::from_secs),
+    };
+    config.exempt_registered = cli.exempt_registered;
+    config.self_test = cli.self_test;
+    config.self_test_fatal = cli.self_test_fatal;
+    config.state_file = cli.state_file;
+    if let Some(secs) = cli.summary_interval {
+        config.summary_interval = (secs > 0).then(|| Duration::from_secs(secs));
+    }
+    config.quiet = cli.quiet;
+    config.registration_log_throttle = match cli.registration_log_throttle {
+        Some(0) => bail!("--registration-log-throttle has to be greater than 0"),
+        secs => secs.map(Duration::from_secs),
+    };
+    if let Some(event_buffer) = cli.event_buffer {
+        config.event_buffer = event_buffer;
+    }
+    if let Some(audit_buffer_size) = cli.audit_buffer_size {
+        config.audit_buffer_size = audit_buffer_size;
+    }
+    #[cfg(unix)]
+    {
+        config.event_socket = cli.event_socket;
+        config.control_socket = cli.control_socket;
+    }
+    config.registration_log = cli.registration_log;
+    match cli.registration_log_max_size {
+        Some(0) => bail!("--registration-log-max-size has to be greater than 0"),
+        Some(max_size) => config.registration_log_max_size = max_size,
+        None => {}
+    }
+
+    if cli.print_config {
+        println!("{}", serde_json::to_string_pretty(&config.summary())?);
+        return Ok(());
+    }
+
+    let server = RendezvousServer::new(config).await?;
+
+    if cli.check {
+        tracing::info!("Configuration is valid");
+        return Ok(());
+    }
+
+    server.run().await?;
+
+    Ok(())
+}
+
+fn parse_multiaddr(s: &str) -> Result<Multiaddr> {
+    s.parse()
+        .map_err(|e| anyhow!("Failed to parse multiaddress {}: {}", s, e))
+}
+
+/// Checks that `address` can be listened on with the TCP and websocket
+/// transports of the server, i.e. it has the form /ip4|ip6/<ip>/tcp/<port>,
+/// optionally followed by /ws or /wss.
+fn validate_listen_address(address: &Multiaddr) -> Result<()> {
+    let mut protocols = address.iter();
+
+    match protocols.next() {
+        Some(Protocol::Ip4(_)) | Some(Protocol::Ip6(_)) => {}
+        Some(Protocol::Memory(_)) => {
+            bail!(
+                "Cannot listen on {}, use --listen-memory for the in-memory transport",
+                address
             )
-            .context("Failed to initialize websocket listener")?;
+        }
+        Some(protocol) => bail!(
+            "Cannot listen on {}, listen addresses have to start with /ip4 or /ip6, not {}",
+            address,
+            protocol
+        ),
+        None => bail!("Cannot listen on an empty address"),
     }
 
-    loop {
-        match swarm.select_next_some().await {
-            SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::PeerRegistered {
-                peer,
-                registration,
-            })) => {
-                tracing::info!(%peer, namespace=%registration.namespace, addresses=?registration.record.addresses(), ttl=registration.ttl,  "Peer registered");
-            }
-            SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::PeerNotRegistered {
-                peer,
-                namespace,
-                error,
-            })) => {
-                tracing::info!(%peer, %namespace, ?error, "Peer failed to register");
-            }
-            SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::RegistrationExpired(
-                registration,
-            ))) => {
-                tracing::info!(peer=%registration.record.peer_id(), namespace=%registration.namespace, addresses=%Addresses(registration.record.addresses()), ttl=registration.ttl, "Registration expired");
-            }
-            SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::PeerUnregistered {
-                peer,
-                namespace,
-            })) => {
-                tracing::info!(%peer, %namespace, "Peer unregistered");
-            }
-            SwarmEvent::Behaviour(Event::Rendezvous(RendezvousEvent::DiscoverServed {
-                enquirer,
-                ..
-            })) => {
-                tracing::info!(peer=%enquirer, "Discovery served");
-            }
-            SwarmEvent::NewListenAddr(address) => {
-                tracing::info!(%address, "New listening address reported");
-            }
-            _ => {}
+    match protocols.next() {
+        Some(Protocol::Tcp(_)) => {}
+        Some(Protocol::Udp(_)) if address.iter().any(|p| matches!(p, Protocol::Quic)) => bail!(
+            "Cannot listen on {}, the QUIC transport is not supported, use /tcp instead",
+            address
+        ),
+        Some(Protocol::Udp(_)) => bail!(
+            "Cannot listen on {}, UDP is not supported, use /tcp optionally followed by /ws or /wss",
+            address
+        ),
+        Some(protocol) => bail!(
+            "Cannot listen on {}, expected /tcp after the IP address, not {}",
+            address,
+            protocol
+        ),
+        None => bail!("Cannot listen on {}, /tcp/<port> is missing", address),
+    }
+
+    let mut websocket = false;
+    for protocol in protocols {
+        match protocol {
+            Protocol::Ws(_) | Protocol::Wss(_) if !websocket => websocket = true,
+            protocol => bail!(
+                "Cannot listen on {}, {} is not supported after /tcp, only /ws or /wss",
+                address,
+                protocol
+            ),
         }
     }
+
+    Ok(())
 }
 
-async fn tls_config_from_params(
-    private_key: Option<PathBuf>,
-    certificate: Option<PathBuf>,
-    websocket: bool,
-) -> Result<Option<tls::Config>> {
-    let (pk, cert) = match (private_key, certificate) {
-        (None, None) => return Ok(None),
-        (Some(pk), Some(cert)) => (pk, cert),
-        _ => bail!("Server private key and certificate both have to be provided"),
-    };
-    if !websocket {
-        tracing::warn!("The provided SSL parameters won't have any affect, because you did not activate websockets");
-        return Ok(None);
+/// Trims whitespace around a namespace and rejects empty namespaces.
+fn parse_namespace(s: &str) -> Result<String> {
+    let namespace = s.trim();
+    if namespace.is_empty() {
+        bail!("Namespace must not be empty");
     }
-    let pk = fs::read(pk).await?;
-    let cert = fs::read(cert).await?;
-    let pk = PrivateKey::new(pk);
-    let cert = Certificate::new(cert);
-    let tls_config = tls::Config::new(pk, vec![cert])?;
 
-    Ok(Some(tls_config))
+    Ok(namespace.to_owned())
 }
 
-fn init_tracing(level: LevelFilter, json_format: bool, no_timestamp: bool) {
-    if level == LevelFilter::OFF {
-        return;
+/// Installs the global subscriber.
+///
+/// Filter directives from the RUST_LOG environment variable take precedence
+/// over `level`, which only applies to the logs of this crate.
+/// `libp2p_level` applies to the logs of all libp2p crates in either case.
+fn init_tracing(
+    level: LevelFilter,
+    libp2p_level: Option<LevelFilter>,
+    json_format: bool,
+    no_timestamp: bool,
+    writer: LogWriter,
+) {
+    let mut env_filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.trim().is_empty() => EnvFilter::new(directives),
+        _ if level == LevelFilter::OFF && libp2p_level.is_none() => return,
+        _ => EnvFilter::new(format!("rendezvous_server={}", level)),
+    };
+    if let Some(libp2p_level) = libp2p_level {
+        // the directive matches all targets starting with libp2p, e.g.
+        // libp2p_swarm
+        env_filter = env_filter.add_directive(
+            format!("libp2p={}", libp2p_level)
+                .parse()
+                .expect("level filter is a valid directive"),
+        );
     }
 
-    let is_terminal = atty::is(atty::Stream::Stderr);
+    // escape codes must not end up in log files
+    let is_terminal = matches!(writer, LogWriter::Stderr) && atty::is(atty::Stream::Stderr);
 
     let builder = FmtSubscriber::builder()
-        .with_env_filter(format!("rendezvous_server={}", level))
-        .with_writer(std::io::stderr)
-        .with_ansi(is_terminal)
+        .with_env_filter(env_filter)
+        .with_writer(writer)
+        // escape codes would end up in the fields of JSON logs
+        .with_ansi(is_terminal && !json_format)
         .with_timer(ChronoLocal::with_format("%F %T".to_owned()))
-        .with_target(false);
+        // tell libp2p logs apart from the logs of the server
+        .with_target(libp2p_level.is_some());
 
     if json_format {
         builder.json().init();
@@ -210,17 +969,120 @@ fn init_tracing(level: LevelFilter, json_format: bool, no_timestamp: bool) {
     builder.init();
 }
 
-async fn load_secret_key_from_file(path: impl AsRef<Path>) -> Result<ed25519::SecretKey> {
+async fn load_secret_key_from_file(
+    path: impl AsRef<Path>,
+    format: Option<SecretFormat>,
+) -> Result<ed25519::SecretKey> {
     let path = path.as_ref();
-    let bytes = fs::read(path)
-        .await
-        .with_context(|| format!("No secret file at {}", path.display()))?;
+    let bytes = match fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            bail!("No secret file at {}", path.display())
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to read secret file {}", path.display()))
+        }
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(path).await?.permissions().mode();
+        if mode & 0o077 != 0 {
+            tracing::warn!(
+                path = %path.display(),
+                mode = %format!("{:o}", mode & 0o777),
+                "Secret file is accessible by other users, restrict its permissions to 600"
+            );
+        }
+    }
+
+    let bytes = decode_secret_key(&bytes, format)
+        .with_context(|| format!("Invalid secret key in {}", path.display()))?;
     let secret_key = ed25519::SecretKey::from_bytes(bytes)?;
 
     Ok(secret_key)
 }
 
-async fn write_secret_key_to_file(secret_key: &ed25519::SecretKey, path: PathBuf) -> Result<()> {
+fn load_port_from_env(var: &str) -> Result<u16> {
+    let value =
+        std::env::var(var).with_context(|| format!("No port in environment variable {}", var))?;
+    let port = value.trim().parse().with_context(|| {
+        format!(
+            "Invalid port {:?} in environment variable {}, expected a number from 0 to 65535",
+            value, var
+        )
+    })?;
+
+    Ok(port)
+}
+
+fn load_secret_key_from_env(var: &str, format: Option<SecretFormat>) -> Result<ed25519::SecretKey> {
+    let value = std::env::var(var)
+        .with_context(|| format!("No secret key in environment variable {}", var))?;
+    let bytes = decode_secret_key(value.as_bytes(), format)
+        .with_context(|| format!("Invalid secret key in environment variable {}", var))?;
+    let secret_key = ed25519::SecretKey::from_bytes(bytes)?;
+
+    Ok(secret_key)
+}
+
+/// Decodes an ed25519 secret key in the given format.
+///
+/// Without a format, exactly 32 bytes are taken as raw key and anything else
+/// is decoded as hex or base64 after trimming surrounding whitespace, e.g. the
+/// trailing newline of a text file.
+fn decode_secret_key(value: &[u8], format: Option<SecretFormat>) -> Result<Vec<u8>> {
+    let format = match format {
+        Some(format) => format,
+        None if value.len() == SECRET_KEY_LENGTH => SecretFormat::Raw,
+        None => match std::str::from_utf8(value) {
+            Ok(text) if is_hex(text.trim()) => SecretFormat::Hex,
+            Ok(_) => SecretFormat::Base64,
+            // binary content can only be a raw key of the wrong length
+            Err(_) => SecretFormat::Raw,
+        },
+    };
+
+    let bytes = match format {
+        SecretFormat::Raw => value.to_vec(),
+        SecretFormat::Hex => {
+            hex::decode(trim_text(value)?).context("Secret key is not valid hex")?
+        }
+        SecretFormat::Base64 => {
+            base64::decode(trim_text(value)?).context("Secret key is not valid base64")?
+        }
+    };
+
+    if bytes.len() != SECRET_KEY_LENGTH {
+        bail!(
+            "Secret key has to be {} bytes long but is {} bytes after decoding it as {}",
+            SECRET_KEY_LENGTH,
+            bytes.len(),
+            format
+        );
+    }
+
+    Ok(bytes)
+}
+
+fn trim_text(value: &[u8]) -> Result<&str> {
+    let text = std::str::from_utf8(value).context("Secret key is binary, not text")?;
+
+    Ok(text.trim())
+}
+
+fn is_hex(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+async fn write_secret_key_to_file(
+    secret_key: &ed25519::SecretKey,
+    path: PathBuf,
+    force: bool,
+) -> Result<()> {
     if let Some(parent) = path.parent() {
         DirBuilder::new()
             .recursive(true)
@@ -233,144 +1095,178 @@ async fn write_secret_key_to_file(secret_key: &ed25519::SecretKey, path: PathBuf
                 )
             })?;
     }
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&path)
-        .await
-        .with_context(|| format!("Could not generate secret file at {}", path.display()))?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    // only the owner should be able to read the server's identity
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = match options.open(&path).await {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists && force => {
+            tracing::warn!(path=%path.display(), "Overwriting existing secret file because of --force, the peer id of the server changes and all references to the old one become invalid");
+
+            return overwrite_secret_key_file(secret_key, &path, &options).await;
+        }
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+            bail!(
+                "Refusing to overwrite existing secret at {}; remove it or omit --generate-secret",
+                path.display()
+            )
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Could not generate secret file at {}", path.display()))
+        }
+    };
 
     file.write_all(secret_key.as_ref()).await?;
 
     Ok(())
 }
 
-fn create_swarm(
-    identity: identity::Keypair,
-    ping: bool,
-    websocket: bool,
-    tls: Option<tls::Config>,
-) -> Result<Swarm<Behaviour>> {
-    let local_peer_id = identity.public().into_peer_id();
-
-    let transport =
-        create_transport(&identity, websocket, tls).context("Failed to create transport")?;
-    let rendezvous = Rendezvous::new(identity, Config::default());
-    let swarm = SwarmBuilder::new(transport, Behaviour::new(rendezvous, ping), local_peer_id)
-        .executor(Box::new(|f| {
-            tokio::spawn(f);
-        }))
-        .build();
-
-    Ok(swarm)
-}
+/// Replaces the secret file at `path` with a new file created through
+/// `options`, so it gets the permissions of a new secret file instead of
+/// keeping those of the old one. The secret is written to a temporary file
+/// first, which is then renamed over the old file.
+async fn overwrite_secret_key_file(
+    secret_key: &ed25519::SecretKey,
+    path: &Path,
+    options: &OpenOptions,
+) -> Result<()> {
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
 
-fn create_transport(
-    identity: &identity::Keypair,
-    websocket: bool,
-    tls: Option<tls::Config>,
-) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
-    let tcp_with_dns = TokioDnsConfig::system(TokioTcpConfig::new().nodelay(true)).unwrap();
-
-    let transport = if websocket {
-        let mut websocket_with_dns = WsConfig::new(tcp_with_dns.clone());
-
-        if let Some(tls) = tls {
-            websocket_with_dns.set_tls_config(tls);
+    // left behind by an interrupted overwrite, possibly with other permissions
+    match fs::remove_file(&tmp_path).await {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => {
+            return Err(error).with_context(|| {
+                format!(
+                    "Could not remove temporary secret file {}",
+                    tmp_path.display()
+                )
+            })
         }
+        _ => {}
+    }
 
-        authenticate_and_multiplex(
-            tcp_with_dns.or_transport(websocket_with_dns).boxed(),
-            &identity,
+    let mut file = options.open(&tmp_path).await.with_context(|| {
+        format!(
+            "Could not create temporary secret file {}",
+            tmp_path.display()
         )
-        .unwrap()
-    } else {
-        authenticate_and_multiplex(tcp_with_dns.boxed(), &identity).unwrap()
-    };
+    })?;
+    file.write_all(secret_key.as_ref()).await?;
+    file.sync_all().await?;
 
-    Ok(transport)
+    fs::rename(&tmp_path, path)
+        .await
+        .with_context(|| format!("Could not overwrite secret file at {}", path.display()))
 }
 
-fn authenticate_and_multiplex<T>(
-    transport: Boxed<T>,
-    identity: &identity::Keypair,
-) -> Result<Boxed<(PeerId, StreamMuxerBox)>>
-where
-    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-{
-    let auth_upgrade = {
-        let noise_identity = noise::Keypair::<X25519Spec>::new().into_authentic(identity)?;
-        NoiseConfig::xx(noise_identity).into_authenticated()
-    };
-    let multiplex_upgrade = SelectUpgrade::new(YamuxConfig::default(), MplexConfig::new());
-
-    let transport = transport
-        .upgrade(Version::V1)
-        .authenticate(auth_upgrade)
-        .multiplex(multiplex_upgrade)
-        .timeout(Duration::from_secs(20))
-        .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
-        .boxed();
-
-    Ok(transport)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[derive(Debug)]
-enum Event {
-    Rendezvous(rendezvous::Event),
-    Ping(PingEvent),
-}
+    #[test]
+    fn decode_secret_key() {
+        let key = [7u8; SECRET_KEY_LENGTH];
+        let hex = "07".repeat(SECRET_KEY_LENGTH);
+        let base64 = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
 
-impl From<rendezvous::Event> for Event {
-    fn from(event: rendezvous::Event) -> Self {
-        Event::Rendezvous(event)
-    }
-}
+        let cases = vec![
+            // detected formats
+            (key.to_vec(), None, Some(key.to_vec())),
+            (hex.as_bytes().to_vec(), None, Some(key.to_vec())),
+            (format!("{}\n", hex).into_bytes(), None, Some(key.to_vec())),
+            (
+                format!("  {}  ", base64).into_bytes(),
+                None,
+                Some(key.to_vec()),
+            ),
+            // 32 bytes are always raw, even if they are valid hex
+            (
+                vec![b'0'; SECRET_KEY_LENGTH],
+                None,
+                Some(vec![b'0'; SECRET_KEY_LENGTH]),
+            ),
+            // explicit formats
+            (key.to_vec(), Some(SecretFormat::Raw), Some(key.to_vec())),
+            (
+                hex.as_bytes().to_vec(),
+                Some(SecretFormat::Hex),
+                Some(key.to_vec()),
+            ),
+            (
+                base64.as_bytes().to_vec(),
+                Some(SecretFormat::Base64),
+                Some(key.to_vec()),
+            ),
+            (hex.as_bytes().to_vec(), Some(SecretFormat::Raw), None),
+            (base64.as_bytes().to_vec(), Some(SecretFormat::Hex), None),
+            // wrong lengths and invalid content
+            (vec![7u8; SECRET_KEY_LENGTH - 1], None, None),
+            (vec![0xffu8; SECRET_KEY_LENGTH + 1], None, None),
+            ("07".repeat(SECRET_KEY_LENGTH - 1).into_bytes(), None, None),
+            (b"not a key".to_vec(), None, None),
+            (Vec::new(), None, None),
+            (
+                vec![0xffu8; SECRET_KEY_LENGTH + 1],
+                Some(SecretFormat::Hex),
+                None,
+            ),
+        ];
 
-impl From<PingEvent> for Event {
-    fn from(event: PingEvent) -> Self {
-        Event::Ping(event)
+        for (value, format, expected) in cases {
+            assert_eq!(
+                super::decode_secret_key(&value, format).ok(),
+                expected,
+                "{:?} as {:?}",
+                value,
+                format
+            );
+        }
     }
-}
 
-#[derive(libp2p::NetworkBehaviour)]
-#[behaviour(event_process = false)]
-#[behaviour(out_event = "Event")]
-struct Behaviour {
-    ping: Toggle<Ping>,
-    rendezvous: Rendezvous,
-}
+    #[test]
+    fn validate_listen_address() {
+        let cases = [
+            ("/ip4/0.0.0.0/tcp/0", None),
+            ("/ip6/::/tcp/4001", None),
+            ("/ip4/127.0.0.1/tcp/80/ws", None),
+            ("/ip6/::1/tcp/443/wss", None),
+            ("/memory/1", Some("use --listen-memory")),
+            (
+                "/dns4/example.com/tcp/4001",
+                Some("have to start with /ip4 or /ip6"),
+            ),
+            (
+                "/ip4/0.0.0.0/udp/4001/quic",
+                Some("QUIC transport is not supported"),
+            ),
+            ("/ip4/0.0.0.0/udp/4001", Some("UDP is not supported")),
+            ("/ip4/0.0.0.0", Some("/tcp/<port> is missing")),
+            ("/ip4/0.0.0.0/ip4/0.0.0.0/tcp/4001", Some("expected /tcp")),
+            ("/ip4/0.0.0.0/tcp/4001/ws/ws", Some("only /ws or /wss")),
+            ("/ip4/0.0.0.0/tcp/4001/ws/wss", Some("only /ws or /wss")),
+            ("/ip4/0.0.0.0/tcp/4001/tcp/4002", Some("only /ws or /wss")),
+        ];
 
-impl Behaviour {
-    fn new(rendezvous: Rendezvous, enable_ping: bool) -> Self {
-        let ping = Toggle::from(enable_ping.then(|| {
-            Ping::new(
-                PingConfig::new()
-                    .with_keep_alive(false)
-                    .with_interval(Duration::from_secs(86_400)),
-            )
-        }));
+        for (address, expected) in cases.iter() {
+            let result = super::validate_listen_address(&address.parse().unwrap());
 
-        Self {
-            // TODO: Remove Ping behaviour once https://github.com/libp2p/rust-libp2p/issues/2109 is fixed
-            // interval for sending Ping set to 24 hours
-            ping,
-            rendezvous,
+            match (result, expected) {
+                (Ok(()), None) => {}
+                (Err(error), Some(expected)) => assert!(
+                    error.to_string().contains(expected),
+                    "{}: {}",
+                    address,
+                    error
+                ),
+                (result, _) => panic!("{}: unexpected result {:?}", address, result),
+            }
         }
-    }
-}
 
-struct Addresses<'a>(&'a [Multiaddr]);
-
-// Prints an array of multiaddresses as a comma seperated string
-impl fmt::Display for Addresses<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let display = self
-            .0
-            .iter()
-            .map(|addr| addr.to_string())
-            .collect::<Vec<String>>()
-            .join(",");
-        write!(f, "{}", display)
+        assert!(super::validate_listen_address(&Multiaddr::empty()).is_err());
     }
 }
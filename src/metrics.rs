@@ -0,0 +1,266 @@
+use anyhow::{Context, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Label value of all namespaces beyond the maximum number of labeled
+/// namespaces
+const OTHER_NAMESPACE: &str = "other";
+
+/// Buckets of the granted TTL histogram in seconds, from one minute to the
+/// maximum TTL of 72 hours allowed by the rendezvous behaviour
+const TTL_BUCKETS: &[f64] = &[
+    60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0, 86400.0, 259200.0,
+];
+
+/// Prometheus metrics of the rendezvous server, updated from the swarm event
+/// loop.
+///
+/// Registration metrics are labeled by namespace. Once `max_namespaces`
+/// namespaces are labeled, all further namespaces are counted as
+/// [`OTHER_NAMESPACE`] to keep the number of time series bounded.
+pub struct Metrics {
+    registry: Registry,
+    peer_registered: IntCounterVec,
+    peer_unregistered: IntCounterVec,
+    registration_expired: IntCounterVec,
+    discover_served: IntCounter,
+    empty_discover: IntCounter,
+    active_registrations: IntGaugeVec,
+    inbound_connection_errors: IntCounter,
+    ping_failures: IntCounter,
+    /// Only recorded if the metrics are served
+    granted_ttl: Option<Histogram>,
+    max_namespaces: usize,
+    namespaces: Mutex<HashSet<String>>,
+}
+
+impl Metrics {
+    pub fn new(max_namespaces: usize, served: bool) -> Result<Self> {
+        let registry = Registry::new();
+
+        let peer_registered = IntCounterVec::new(
+            Opts::new(
+                "rendezvous_peer_registered_total",
+                "Number of successful peer registrations",
+            ),
+            &["namespace"],
+        )?;
+        let peer_unregistered = IntCounterVec::new(
+            Opts::new(
+                "rendezvous_peer_unregistered_total",
+                "Number of peers that unregistered",
+            ),
+            &["namespace"],
+        )?;
+        let registration_expired = IntCounterVec::new(
+            Opts::new(
+                "rendezvous_registration_expired_total",
+                "Number of registrations that expired",
+            ),
+            &["namespace"],
+        )?;
+        let discover_served = IntCounter::new(
+            "rendezvous_discover_served_total",
+            "Number of served discover requests",
+        )?;
+        let empty_discover = IntCounter::new(
+            "rendezvous_empty_discover_total",
+            "Number of served discover requests that returned no registrations",
+        )?;
+        let active_registrations = IntGaugeVec::new(
+            Opts::new(
+                "rendezvous_active_registrations",
+                "Number of currently active registrations",
+            ),
+            &["namespace"],
+        )?;
+        let inbound_connection_errors = IntCounter::new(
+            "rendezvous_inbound_connection_errors_total",
+            "Number of incoming connections that failed before being established",
+        )?;
+        let ping_failures = IntCounter::new(
+            "rendezvous_ping_failures_total",
+            "Number of failed pings to connected peers",
+        )?;
+
+        registry.register(Box::new(peer_registered.clone()))?;
+        registry.register(Box::new(peer_unregistered.clone()))?;
+        registry.register(Box::new(registration_expired.clone()))?;
+        registry.register(Box::new(discover_served.clone()))?;
+        registry.register(Box::new(empty_discover.clone()))?;
+        registry.register(Box::new(active_registrations.clone()))?;
+        registry.register(Box::new(inbound_connection_errors.clone()))?;
+        registry.register(Box::new(ping_failures.clone()))?;
+
+        let granted_ttl = if served {
+            let granted_ttl = Histogram::with_opts(
+                HistogramOpts::new(
+                    "rendezvous_granted_ttl_seconds",
+                    "TTLs of the accepted registrations",
+                )
+                .buckets(TTL_BUCKETS.to_vec()),
+            )?;
+            registry.register(Box::new(granted_ttl.clone()))?;
+
+            Some(granted_ttl)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            registry,
+            peer_registered,
+            peer_unregistered,
+            registration_expired,
+            discover_served,
+            empty_discover,
+            active_registrations,
+            inbound_connection_errors,
+            ping_failures,
+            granted_ttl,
+            max_namespaces,
+            namespaces: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn peer_registered(&self, namespace: &str, refresh: bool) {
+        let namespace = self.label(namespace);
+        self.peer_registered.with_label_values(&[namespace]).inc();
+        if !refresh {
+            self.active_registrations
+                .with_label_values(&[namespace])
+                .inc();
+        }
+    }
+
+    pub fn peer_unregistered(&self, namespace: &str) {
+        let namespace = self.label(namespace);
+        self.peer_unregistered.with_label_values(&[namespace]).inc();
+        self.active_registrations
+            .with_label_values(&[namespace])
+            .dec();
+    }
+
+    pub fn registration_expired(&self, namespace: &str) {
+        let namespace = self.label(namespace);
+        self.registration_expired
+            .with_label_values(&[namespace])
+            .inc();
+        self.active_registrations
+            .with_label_values(&[namespace])
+            .dec();
+    }
+
+    pub fn registration_evicted(&self, namespace: &str) {
+        let namespace = self.label(namespace);
+        self.active_registrations
+            .with_label_values(&[namespace])
+            .dec();
+    }
+
+    pub fn discover_served(&self) {
+        self.discover_served.inc();
+    }
+
+    pub fn empty_discover(&self) {
+        self.empty_discover.inc();
+    }
+
+    pub fn inbound_connection_error(&self) {
+        self.inbound_connection_errors.inc();
+    }
+
+    pub fn ping_failure(&self) {
+        self.ping_failures.inc();
+    }
+
+    pub fn granted_ttl(&self, ttl: u64) {
+        if let Some(granted_ttl) = &self.granted_ttl {
+            granted_ttl.observe(ttl as f64);
+        }
+    }
+
+    /// Returns the label value for `namespace`, labeling it if the maximum
+    /// number of labeled namespaces is not reached yet.
+    fn label<'a>(&self, namespace: &'a str) -> &'a str {
+        let mut namespaces = self
+            .namespaces
+            .lock()
+            .expect("metrics are not updated after a panic");
+
+        if namespaces.contains(namespace) {
+            return namespace;
+        }
+        if namespaces.len() < self.max_namespaces {
+            namespaces.insert(namespace.to_owned());
+            return namespace;
+        }
+
+        OTHER_NAMESPACE
+    }
+
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+/// Binds an HTTP server to `address` and serves the metrics on `/metrics` in a
+/// separate task.
+pub fn spawn_server(address: SocketAddr, metrics: Arc<Metrics>) -> Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(request, &metrics);
+
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .with_context(|| format!("Failed to bind metrics server to {}", address))?
+        .serve(make_service);
+
+    tracing::info!(%address, "Serving metrics");
+
+    tokio::spawn(async move {
+        if let Err(error) = server.await {
+            tracing::error!(%error, "Metrics server failed");
+        }
+    });
+
+    Ok(())
+}
+
+fn handle(request: Request<Body>, metrics: &Metrics) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => match metrics.encode() {
+            Ok(body) => Response::new(Body::from(body)),
+            Err(error) => {
+                tracing::error!(%error, "Failed to encode metrics");
+                status(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        },
+        _ => status(StatusCode::NOT_FOUND),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+
+    response
+}
@@ -0,0 +1,264 @@
+use crate::event::ServerEvent;
+use crate::registrations::{unix_timestamp, Registrations};
+use anyhow::{bail, Context, Result};
+use std::cmp;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TryRecvError;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Maximum length of a line sent by the primary in bytes, including the
+/// newline
+const MAX_LINE_LENGTH: u64 = 64 * 1024;
+/// Interval at which expired mirrored registrations are removed
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Streams the rendezvous events as JSON lines to the standby at `address`
+/// in a separate task. Returns the sender events are forwarded to, at most
+/// `buffer` events are queued.
+///
+/// `secret` is sent as the first line of every connection to authenticate
+/// the primary to the standby.
+///
+/// The connection is re-established with exponential backoff when it is
+/// lost. Events handled while disconnected are dropped, so the standby may be
+/// stale after a gap until the affected registrations are refreshed or
+/// expire.
+pub fn spawn_sender(
+    address: SocketAddr,
+    secret: String,
+    buffer: usize,
+) -> mpsc::Sender<ServerEvent> {
+    let (sender, receiver) = mpsc::channel(buffer);
+    tokio::spawn(send(address, secret, receiver));
+
+    sender
+}
+
+async fn send(address: SocketAddr, secret: String, mut events: mpsc::Receiver<ServerEvent>) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut dropped = 0u64;
+    let mut connected_before = false;
+
+    loop {
+        let mut stream = match connect(address, &secret).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                tracing::warn!(%address, %error, retry_secs=backoff.as_secs(), "Failed to connect to mirror");
+                tokio::time::sleep(backoff).await;
+                backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+                if !drain(&mut events, &mut dropped) {
+                    return;
+                }
+                continue;
+            }
+        };
+        backoff = INITIAL_BACKOFF;
+
+        if connected_before || dropped > 0 {
+            tracing::warn!(%address, dropped, "Reconnected to mirror, the mirror may be stale until registrations are refreshed");
+        } else {
+            tracing::info!(%address, "Mirroring registrations");
+        }
+        connected_before = true;
+        dropped = 0;
+
+        loop {
+            let event = match events.recv().await {
+                Some(event) => event,
+                None => return,
+            };
+            let mut line = match serde_json::to_string(&event) {
+                Ok(line) => line,
+                Err(error) => {
+                    tracing::warn!(%error, "Failed to serialize event");
+                    continue;
+                }
+            };
+            line.push('\n');
+
+            if let Err(error) = stream.write_all(line.as_bytes()).await {
+                tracing::warn!(%address, %error, "Lost connection to mirror");
+                dropped += 1;
+                break;
+            }
+        }
+    }
+}
+
+/// Connects to the standby at `address` and authenticates with `secret`.
+async fn connect(address: SocketAddr, secret: &str) -> std::io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(address).await?;
+    stream.write_all(format!("{}\n", secret).as_bytes()).await?;
+
+    Ok(stream)
+}
+
+/// Drops the queued events while disconnected and counts them in `dropped`.
+/// Returns false if the sender was dropped.
+fn drain(events: &mut mpsc::Receiver<ServerEvent>, dropped: &mut u64) -> bool {
+    loop {
+        match events.try_recv() {
+            Ok(_) => *dropped += 1,
+            Err(TryRecvError::Empty) => return true,
+            Err(TryRecvError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Binds `address` and applies the events streamed by a primary server to
+/// `registrations` in a separate task.
+///
+/// Primaries have to send `secret` as the first line, connections of
+/// primaries sending another secret are closed.
+///
+/// Like registrations restored from a state file, mirrored registrations are
+/// only tracked and cannot be discovered until the peers register with this
+/// server. The primary does not report the expiry of registrations that
+/// expired while it was disconnected, so expired registrations are removed
+/// periodically.
+pub async fn spawn_receiver(
+    address: SocketAddr,
+    secret: String,
+    registrations: Arc<RwLock<Registrations>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to bind mirror listener to {}", address))?;
+
+    tracing::info!(%address, "Receiving mirrored registrations");
+
+    let pruned = registrations.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let count = pruned
+                .write()
+                .expect("registrations are not updated after a panic")
+                .prune(unix_timestamp());
+            if count > 0 {
+                tracing::debug!(count, "Removed expired mirrored registrations");
+            }
+        }
+    });
+
+    let secret = Arc::new(secret);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, primary)) => {
+                    tokio::spawn(receive(
+                        stream,
+                        primary,
+                        secret.clone(),
+                        registrations.clone(),
+                    ));
+                }
+                Err(error) => {
+                    tracing::warn!(%error, "Failed to accept mirror connection");
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn receive(
+    stream: TcpStream,
+    primary: SocketAddr,
+    secret: Arc<String>,
+    registrations: Arc<RwLock<Registrations>>,
+) {
+    let mut reader = BufReader::new(stream);
+
+    let authenticated = match next_line(&mut reader).await {
+        Ok(Some(line)) => {
+            ring::constant_time::verify_slices_are_equal(line.as_bytes(), secret.as_bytes()).is_ok()
+        }
+        Ok(None) => false,
+        Err(error) => {
+            tracing::warn!(%primary, ?error, "Failed to read secret from primary");
+            return;
+        }
+    };
+    if !authenticated {
+        tracing::warn!(%primary, "Rejected mirror connection, invalid secret");
+        return;
+    }
+    tracing::info!(%primary, "Primary connected, mirroring its registrations");
+
+    loop {
+        let line = match next_line(&mut reader).await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(error) => {
+                tracing::warn!(%primary, ?error, "Failed to read from primary");
+                break;
+            }
+        };
+        let event = match serde_json::from_str::<ServerEvent>(&line) {
+            Ok(event) => event,
+            Err(error) => {
+                tracing::warn!(%primary, %error, "Ignoring invalid mirrored event");
+                continue;
+            }
+        };
+
+        tracing::debug!(%primary, ?event, "Applying mirrored event");
+        apply(
+            &mut registrations
+                .write()
+                .expect("registrations are not updated after a panic"),
+            event,
+        );
+    }
+
+    tracing::warn!(%primary, "Primary disconnected, mirrored registrations may be stale");
+}
+
+/// Reads the next line of at most [`MAX_LINE_LENGTH`] bytes without the
+/// newline. Returns `None` at the end of the stream.
+async fn next_line(reader: &mut BufReader<TcpStream>) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(MAX_LINE_LENGTH)
+        .read_until(b'\n', &mut line)
+        .await?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if read as u64 == MAX_LINE_LENGTH {
+        bail!("Line exceeds {} bytes", MAX_LINE_LENGTH);
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .context("Line is not valid UTF-8")
+}
+
+fn apply(registrations: &mut Registrations, event: ServerEvent) {
+    match event {
+        ServerEvent::PeerRegistered {
+            peer,
+            namespace,
+            addresses,
+            ttl,
+        } => registrations.insert(peer, namespace, addresses, ttl, None, true),
+        ServerEvent::PeerUnregistered { peer, namespace }
+        | ServerEvent::RegistrationExpired {
+            peer, namespace, ..
+        } => registrations.remove(&peer, &namespace),
+        ServerEvent::DiscoverServed { .. } => {}
+    }
+}
@@ -0,0 +1,143 @@
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+/// Number of remote IPs peers have to report the same address from before it
+/// is announced
+const MIN_REPORTING_IPS: usize = 2;
+
+/// Addresses connected peers observed the server on, as reported through
+/// identify.
+///
+/// Each peer counts once with the address it reported last. Peer ids cost
+/// nothing to create, so reports are counted per remote IP of the reporting
+/// connection: many peers connecting from the same IP count as one. Addresses
+/// on a port the server does not listen on are ignored. The most common
+/// address is promoted to an external address once it was reported from
+/// enough IPs.
+#[derive(Debug, Default)]
+pub struct ObservedAddresses {
+    reports: HashMap<PeerId, (IpAddr, Multiaddr)>,
+    promoted: Option<Multiaddr>,
+}
+
+/// Observed address that became the most common one.
+#[derive(Debug)]
+pub struct Promotion {
+    pub address: Multiaddr,
+    /// Number of remote IPs the address was reported from
+    pub ips: usize,
+    /// Previously promoted address that is replaced
+    pub replaced: Option<Multiaddr>,
+}
+
+impl ObservedAddresses {
+    /// Records that `peer`, connected from `remote_ip`, observed the server on
+    /// `address` and returns the most common address if it changed. The
+    /// report is ignored unless `address` has the TCP port of one of the
+    /// `listen_addresses`.
+    pub fn record<'a>(
+        &mut self,
+        peer: PeerId,
+        remote_ip: IpAddr,
+        address: Multiaddr,
+        listen_addresses: impl IntoIterator<Item = &'a Multiaddr>,
+    ) -> Option<Promotion> {
+        let port = tcp_port(&address)?;
+        if !listen_addresses
+            .into_iter()
+            .any(|listen_address| tcp_port(listen_address) == Some(port))
+        {
+            tracing::debug!(%peer, %address, "Ignoring observed address on a port the server does not listen on");
+            return None;
+        }
+
+        self.reports.insert(peer, (remote_ip, address));
+
+        let mut counts = HashMap::<&Multiaddr, HashSet<IpAddr>>::new();
+        for (ip, address) in self.reports.values() {
+            counts.entry(address).or_default().insert(*ip);
+        }
+        // the promoted address is only replaced by a more common one, so ties
+        // do not flip between addresses
+        let promoted_ips = self
+            .promoted
+            .as_ref()
+            .and_then(|promoted| counts.get(promoted).map(HashSet::len))
+            .unwrap_or_default();
+        let (address, ips) = counts
+            .into_iter()
+            .map(|(address, ips)| (address, ips.len()))
+            .max_by_key(|(_, ips)| *ips)?;
+
+        if ips < MIN_REPORTING_IPS || ips <= promoted_ips {
+            return None;
+        }
+
+        let address = address.clone();
+        let replaced = self.promoted.replace(address.clone());
+
+        Some(Promotion {
+            address,
+            ips,
+            replaced,
+        })
+    }
+
+    /// Forgets the report of a disconnected peer.
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.reports.remove(peer);
+    }
+}
+
+fn tcp_port(address: &Multiaddr) -> Option<u16> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Tcp(port) => Some(port),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn promotes_address_reported_from_distinct_ips() {
+        let listen_addresses = ["/ip4/10.0.0.5/tcp/8888".parse::<Multiaddr>().unwrap()];
+        let observed = "/ip4/1.2.3.4/tcp/8888".parse::<Multiaddr>().unwrap();
+        let other_port = "/ip4/1.2.3.4/tcp/40000".parse::<Multiaddr>().unwrap();
+        let first_ip = "5.6.7.8".parse::<IpAddr>().unwrap();
+        let second_ip = "9.10.11.12".parse::<IpAddr>().unwrap();
+        let mut addresses = ObservedAddresses::default();
+
+        // peers behind the same IP count once
+        for _ in 0..3 {
+            assert!(addresses
+                .record(
+                    PeerId::random(),
+                    first_ip,
+                    observed.clone(),
+                    &listen_addresses
+                )
+                .is_none());
+        }
+        // ports the server does not listen on are ignored
+        assert!(addresses
+            .record(PeerId::random(), second_ip, other_port, &listen_addresses)
+            .is_none());
+
+        let promotion = addresses
+            .record(
+                PeerId::random(),
+                second_ip,
+                observed.clone(),
+                &listen_addresses,
+            )
+            .unwrap();
+
+        assert_eq!(promotion.address, observed);
+        assert_eq!(promotion.ips, 2);
+        assert!(promotion.replaced.is_none());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::log_file::{RotatingFile, DEFAULT_LOG_MAX_FILES};
+use crate::registrations::unix_timestamp;
+use anyhow::{Context, Result};
+use libp2p::rendezvous::Registration;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Appends registration lifecycle events as JSON lines to a file, e.g. for
+/// an analytics pipeline.
+///
+/// Each line is flushed right away so the file can be tailed. The file is
+/// rotated like the log file once it would exceed the maximum size.
+pub struct RegistrationLog {
+    path: PathBuf,
+    file: RotatingFile,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    Registered,
+    Unregistered,
+    Expired,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    /// Seconds since the unix epoch at which the event was handled
+    timestamp: u64,
+    #[serde(rename = "type")]
+    kind: Kind,
+    peer_id: String,
+    namespace: &'a str,
+    addresses: &'a [Multiaddr],
+    ttl: Option<u64>,
+}
+
+impl RegistrationLog {
+    pub fn open(path: PathBuf, max_size: u64) -> Result<Self> {
+        let file = RotatingFile::open(path.clone(), max_size, DEFAULT_LOG_MAX_FILES)
+            .with_context(|| format!("Failed to open registration log {}", path.display()))?;
+
+        Ok(Self { path, file })
+    }
+
+    pub fn registered(&mut self, registration: &Registration) {
+        self.write_registration(Kind::Registered, registration);
+    }
+
+    pub fn expired(&mut self, registration: &Registration) {
+        self.write_registration(Kind::Expired, registration);
+    }
+
+    pub fn unregistered(&mut self, peer: PeerId, namespace: &str) {
+        self.write(Entry {
+            timestamp: unix_timestamp(),
+            kind: Kind::Unregistered,
+            peer_id: peer.to_string(),
+            namespace,
+            addresses: &[],
+            ttl: None,
+        });
+    }
+
+    fn write_registration(&mut self, kind: Kind, registration: &Registration) {
+        self.write(Entry {
+            timestamp: unix_timestamp(),
+            kind,
+            peer_id: registration.record.peer_id().to_string(),
+            namespace: &registration.namespace.to_string(),
+            addresses: registration.record.addresses(),
+            ttl: Some(registration.ttl),
+        });
+    }
+
+    fn write(&mut self, entry: Entry<'_>) {
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(error) => {
+                tracing::warn!(%error, "Failed to encode registration log entry");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(error) = self.file.write_all(&line).and_then(|()| self.file.flush()) {
+            tracing::warn!(path=%self.path.display(), %error, "Failed to write registration log");
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+
+/// Registrations known to the server, tracked in parallel to the store of the
+/// rendezvous behaviour which is not accessible from the outside.
+///
+/// Registrations restored from a state file cannot be handed back to the
+/// rendezvous behaviour. They are kept until they expire or the peer registers
+/// again, which keeps the state file stable across restarts.
+#[derive(Debug, Default, Clone)]
+pub struct Registrations {
+    entries: HashMap<(PeerId, String), Registration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Registration {
+    pub peer_id: String,
+    pub namespace: String,
+    pub addresses: Vec<Multiaddr>,
+    pub ttl: u64,
+    /// Seconds since the unix epoch at which the registration was received.
+    pub timestamp: u64,
+    /// Base64 encoded signed envelope of the peer record as sent by the peer.
+    /// Missing in state files written by earlier versions and for mirrored
+    /// registrations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_record: Option<String>,
+    /// Whether the registration was restored from a state file or mirrored
+    /// from a primary server. The rendezvous behaviour does not serve these
+    /// registrations to discoverers until the peer registers again.
+    #[serde(default)]
+    pub restored: bool,
+}
+
+impl Registration {
+    fn is_expired(&self, now: u64) -> bool {
+        self.timestamp.saturating_add(self.ttl) <= now
+    }
+
+    /// Seconds until the registration expires, zero if it already expired.
+    pub fn remaining_ttl(&self, now: u64) -> u64 {
+        self.timestamp.saturating_add(self.ttl).saturating_sub(now)
+    }
+}
+
+impl Registrations {
+    /// Tracks the registration of `peer` in `namespace`. `signed_record` is
+    /// the protobuf encoding of the signed envelope of the peer record, which
+    /// mirrored registrations don't have. `restored` marks registrations the
+    /// rendezvous behaviour does not serve, e.g. mirrored ones.
+    pub fn insert(
+        &mut self,
+        peer: PeerId,
+        namespace: String,
+        addresses: Vec<Multiaddr>,
+        ttl: u64,
+        signed_record: Option<Vec<u8>>,
+        restored: bool,
+    ) {
+        let registration = Registration {
+            peer_id: peer.to_string(),
+            namespace: namespace.clone(),
+            addresses,
+            ttl,
+            timestamp: unix_timestamp(),
+            signed_record: signed_record.map(base64::encode),
+            restored,
+        };
+
+        self.entries.insert((peer, namespace), registration);
+    }
+
+    pub fn remove(&mut self, peer: &PeerId, namespace: &str) {
+        self.entries.remove(&(*peer, namespace.to_owned()));
+    }
+
+    /// Removes all registrations matching `predicate` and returns their peers
+    /// and namespaces.
+    pub fn evict(
+        &mut self,
+        mut predicate: impl FnMut(&PeerId, &str) -> bool,
+    ) -> Vec<(PeerId, String)> {
+        let evicted = self
+            .entries
+            .keys()
+            .filter(|(peer, namespace)| predicate(peer, namespace))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in &evicted {
+            self.entries.remove(key);
+        }
+
+        evicted
+    }
+
+    /// Removes all registrations that expired at `now` and returns their
+    /// number, for registrations whose expiry is not reported, e.g. mirrored
+    /// ones.
+    pub fn prune(&mut self, now: u64) -> usize {
+        let before = self.entries.len();
+        self.entries
+            .retain(|_, registration| !registration.is_expired(now));
+
+        before - self.entries.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns all registrations that have not expired at `now`.
+    pub fn active(&self, now: u64) -> impl Iterator<Item = &Registration> {
+        self.entries
+            .values()
+            .filter(move |registration| !registration.is_expired(now))
+    }
+
+    /// Loads registrations from a state file, dropping those that expired in
+    /// the meantime. The loaded registrations are marked as restored.
+    ///
+    /// A missing state file results in an empty set of registrations.
+    pub async fn load(path: &Path) -> Result<Self> {
+        let bytes = match fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read state file {}", path.display()))
+            }
+        };
+        let registrations = serde_json::from_slice::<Vec<Registration>>(&bytes)
+            .with_context(|| format!("Failed to parse state file {}", path.display()))?;
+
+        let now = unix_timestamp();
+        let mut entries = HashMap::new();
+
+        for mut registration in registrations {
+            if registration.is_expired(now) {
+                continue;
+            }
+
+            let peer = match registration.peer_id.parse::<PeerId>() {
+                Ok(peer) => peer,
+                Err(_) => {
+                    tracing::warn!(peer=%registration.peer_id, "Skipping registration with invalid peer id in state file");
+                    continue;
+                }
+            };
+
+            registration.restored = true;
+            entries.insert((peer, registration.namespace.clone()), registration);
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Writes all registrations that have not yet expired to a state file.
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let registrations = self.active(unix_timestamp()).collect::<Vec<_>>();
+        let bytes = serde_json::to_vec_pretty(&registrations)?;
+
+        // write to a temporary file first to not leave a truncated state file
+        // behind if we get interrupted
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        fs::write(&tmp_path, bytes)
+            .await
+            .with_context(|| format!("Failed to write state file {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .await
+            .with_context(|| format!("Failed to write state file {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is after the unix epoch")
+        .as_secs()
+}
@@ -0,0 +1,47 @@
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Peers with an active registration, grouped by namespace.
+#[derive(Debug, Default)]
+pub struct Namespaces {
+    peers: HashMap<String, HashSet<PeerId>>,
+}
+
+impl Namespaces {
+    /// Returns whether `peer` was not registered in `namespace` yet, i.e.
+    /// the registration is not a refresh.
+    pub fn add(&mut self, namespace: String, peer: PeerId) -> bool {
+        self.peers.entry(namespace).or_default().insert(peer)
+    }
+
+    pub fn remove(&mut self, namespace: &str, peer: &PeerId) {
+        if let Some(peers) = self.peers.get_mut(namespace) {
+            peers.remove(peer);
+
+            if peers.is_empty() {
+                self.peers.remove(namespace);
+            }
+        }
+    }
+}
+
+// Prints the number of peers per namespace, e.g. "namespace=foo peers=12,
+// namespace=bar peers=3"
+impl fmt::Display for Namespaces {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.peers.is_empty() {
+            return write!(f, "none");
+        }
+
+        let mut namespaces = self.peers.iter().collect::<Vec<_>>();
+        namespaces.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let display = namespaces
+            .into_iter()
+            .map(|(namespace, peers)| format!("namespace={} peers={}", namespace, peers.len()))
+            .collect::<Vec<String>>()
+            .join(", ");
+        write!(f, "{}", display)
+    }
+}
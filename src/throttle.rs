@@ -0,0 +1,205 @@
+use libp2p::PeerId;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Sliding window in which the discover requests and connections of a peer
+/// are counted
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts the discover requests per peer within a sliding window of one
+/// minute and tracks the peers exceeding the limit.
+#[derive(Debug)]
+pub struct DiscoverLimiter {
+    limit: u32,
+    requests: HashMap<PeerId, VecDeque<Instant>>,
+    throttled: HashSet<PeerId>,
+}
+
+impl DiscoverLimiter {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            requests: HashMap::new(),
+            throttled: HashSet::new(),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn is_throttled(&self, peer: &PeerId) -> bool {
+        self.throttled.contains(peer)
+    }
+
+    /// Records a discover request of `peer`. Returns true if the peer exceeds
+    /// the limit and was not throttled before.
+    pub fn record(&mut self, peer: PeerId, now: Instant) -> bool {
+        let requests = self.requests.entry(peer).or_default();
+        requests.push_back(now);
+
+        requests.len() > self.limit as usize && self.throttled.insert(peer)
+    }
+
+    /// Forgets the requests that left the window. Returns the throttled peers
+    /// that are within the limit again.
+    pub fn decay(&mut self, now: Instant) -> Vec<PeerId> {
+        forget_outside_window(&mut self.requests, now);
+
+        let limit = self.limit as usize;
+        let requests = &self.requests;
+        let released = self
+            .throttled
+            .iter()
+            .filter(|peer| requests.get(*peer).map_or(0, VecDeque::len) <= limit)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for peer in &released {
+            self.throttled.remove(peer);
+        }
+
+        released
+    }
+}
+
+/// Counts the connections established by each peer within a sliding window of
+/// one minute to detect peers reconnecting in a loop.
+///
+/// Peers exceeding the limit are flagged until `ban_duration` passed if set,
+/// otherwise until they are within the limit again.
+#[derive(Debug)]
+pub struct ReconnectLimiter {
+    limit: u32,
+    ban_duration: Option<Duration>,
+    connections: HashMap<PeerId, VecDeque<Instant>>,
+    flagged: HashMap<PeerId, Instant>,
+}
+
+impl ReconnectLimiter {
+    pub fn new(limit: u32, ban_duration: Option<Duration>) -> Self {
+        Self {
+            limit,
+            ban_duration,
+            connections: HashMap::new(),
+            flagged: HashMap::new(),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn ban_duration(&self) -> Option<Duration> {
+        self.ban_duration
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.ban_duration.is_some() && self.flagged.contains_key(peer)
+    }
+
+    /// Records an established connection of `peer`. Returns true if the peer
+    /// exceeds the limit and was not flagged before.
+    pub fn record(&mut self, peer: PeerId, now: Instant) -> bool {
+        let connections = self.connections.entry(peer).or_default();
+        connections.push_back(now);
+
+        if connections.len() <= self.limit as usize || self.flagged.contains_key(&peer) {
+            return false;
+        }
+
+        self.flagged.insert(peer, now);
+        true
+    }
+
+    /// Forgets the connections that left the window. Returns the flagged
+    /// peers whose ban expired or, without ban, that are within the limit
+    /// again.
+    pub fn decay(&mut self, now: Instant) -> Vec<PeerId> {
+        forget_outside_window(&mut self.connections, now);
+
+        let limit = self.limit as usize;
+        let ban_duration = self.ban_duration;
+        let connections = &self.connections;
+        let released = self
+            .flagged
+            .iter()
+            .filter(|(peer, flagged_at)| match ban_duration {
+                Some(ban_duration) => now.duration_since(**flagged_at) >= ban_duration,
+                None => connections.get(*peer).map_or(0, VecDeque::len) <= limit,
+            })
+            .map(|(peer, _)| *peer)
+            .collect::<Vec<_>>();
+
+        for peer in &released {
+            self.flagged.remove(peer);
+        }
+
+        released
+    }
+}
+
+/// Suppresses the log lines of registrations refreshed within `window` of
+/// the last logged one for the same peer and namespace.
+///
+/// New registrations are always logged.
+#[derive(Debug)]
+pub struct RefreshLogThrottle {
+    window: Duration,
+    last_logged: HashMap<(PeerId, String), Instant>,
+    suppressed: usize,
+}
+
+impl RefreshLogThrottle {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_logged: HashMap::new(),
+            suppressed: 0,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Returns whether the registration of `peer` in `namespace` is logged,
+    /// counting it as suppressed otherwise.
+    pub fn should_log(&mut self, peer: PeerId, namespace: &str, now: Instant) -> bool {
+        match self.last_logged.get_mut(&(peer, namespace.to_owned())) {
+            Some(last_logged) if now.duration_since(*last_logged) < self.window => {
+                self.suppressed += 1;
+                false
+            }
+            Some(last_logged) => {
+                *last_logged = now;
+                true
+            }
+            None => {
+                self.last_logged.insert((peer, namespace.to_owned()), now);
+                true
+            }
+        }
+    }
+
+    /// Forgets a registration that went away, so registering again is logged
+    /// as new registration.
+    pub fn remove(&mut self, peer: PeerId, namespace: &str) {
+        self.last_logged.remove(&(peer, namespace.to_owned()));
+    }
+
+    /// Returns the number of suppressed log lines since the last call.
+    pub fn take_suppressed(&mut self) -> usize {
+        std::mem::take(&mut self.suppressed)
+    }
+}
+
+fn forget_outside_window(events: &mut HashMap<PeerId, VecDeque<Instant>>, now: Instant) {
+    events.retain(|_, events| {
+        while matches!(events.front(), Some(time) if now.duration_since(*time) >= WINDOW) {
+            events.pop_front();
+        }
+
+        !events.is_empty()
+    });
+}
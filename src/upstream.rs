@@ -0,0 +1,192 @@
+use crate::Behaviour;
+use anyhow::{anyhow, bail, Context, Result};
+use libp2p::multiaddr::Protocol;
+use libp2p::rendezvous::Namespace;
+use libp2p::{Multiaddr, PeerId, Swarm};
+use std::cmp;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Delay before the first retry after failing to reach the upstream, doubled
+/// on each consecutive failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Another rendezvous server the server registers itself with.
+///
+/// The upstream is dialed whenever the registrations have to be sent and the
+/// upstream is not connected, the connection is not kept open in between.
+/// Registrations are refreshed after half of their TTL.
+pub struct Upstream {
+    peer: PeerId,
+    address: Multiaddr,
+    namespaces: Vec<Namespace>,
+    backoff: Duration,
+    connected: bool,
+    /// Time at which the dial in progress was started
+    dialing: Option<Instant>,
+    /// Number of registrations sent without a response yet
+    in_flight: usize,
+    /// Whether one of the registrations in flight failed
+    failed: bool,
+    /// Time at which the registrations are sent next
+    deadline: Option<Instant>,
+}
+
+impl Upstream {
+    /// Creates an upstream for `address`, which has to end with
+    /// `/p2p/<peer id>`.
+    pub fn new(address: Multiaddr, namespaces: Vec<String>) -> Result<Self> {
+        let peer = match address.iter().last() {
+            Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash)
+                .map_err(|_| anyhow!("Invalid peer id in upstream address {}", address))?,
+            _ => bail!(
+                "Upstream address {} has to end with /p2p/<peer id>",
+                address
+            ),
+        };
+        if namespaces.is_empty() {
+            bail!("--upstream requires at least one --upstream-namespace");
+        }
+        let namespaces = namespaces
+            .into_iter()
+            .map(|namespace| {
+                Namespace::new(namespace.clone())
+                    .with_context(|| format!("Invalid upstream namespace {}", namespace))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            peer,
+            address,
+            namespaces,
+            backoff: INITIAL_BACKOFF,
+            connected: false,
+            dialing: None,
+            in_flight: 0,
+            failed: false,
+            deadline: Some(Instant::now()),
+        })
+    }
+
+    pub fn peer(&self) -> PeerId {
+        self.peer
+    }
+
+    pub fn address(&self) -> &Multiaddr {
+        &self.address
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Sends the registrations or dials the upstream to send them once
+    /// connected.
+    pub fn on_deadline(&mut self, swarm: &mut Swarm<Behaviour>) {
+        self.deadline = None;
+        if self.dialing.is_some() || self.in_flight > 0 {
+            return;
+        }
+
+        if self.connected {
+            self.register(swarm);
+            return;
+        }
+
+        tracing::debug!(address=%self.address, "Dialing upstream rendezvous server");
+        match swarm.dial_addr(self.address.clone()) {
+            Ok(()) => self.dialing = Some(Instant::now()),
+            Err(error) => {
+                tracing::warn!(address=%self.address, ?error, "Failed to dial upstream rendezvous server");
+                self.retry();
+            }
+        }
+    }
+
+    pub fn on_connection_established(&mut self, swarm: &mut Swarm<Behaviour>) {
+        self.connected = true;
+
+        if self.dialing.take().is_some() {
+            self.register(swarm);
+        }
+    }
+
+    pub fn on_connection_closed(&mut self) {
+        self.connected = false;
+
+        // registrations in flight are lost with the connection
+        if self.in_flight > 0 {
+            tracing::warn!(address=%self.address, "Connection to upstream rendezvous server closed before registering");
+            self.in_flight = 0;
+            self.failed = false;
+            self.retry();
+        }
+    }
+
+    pub fn on_dial_failure(&mut self, error: impl std::fmt::Display) {
+        let started = match self.dialing.take() {
+            Some(started) => started,
+            None => return,
+        };
+
+        tracing::warn!(address=%self.address, %error, elapsed_ms=started.elapsed().as_millis() as u64, "Failed to reach upstream rendezvous server");
+        self.retry();
+    }
+
+    pub fn on_registered(&mut self, namespace: &Namespace, ttl: u64) {
+        let refresh = Duration::from_secs(ttl / 2);
+        tracing::info!(address=%self.address, %namespace, ttl, refresh_secs=refresh.as_secs(), "Registered with upstream rendezvous server");
+
+        self.schedule(Instant::now() + refresh);
+        self.response_received();
+    }
+
+    pub fn on_register_failed(&mut self, error: impl std::fmt::Debug) {
+        tracing::warn!(address=%self.address, ?error, "Failed to register with upstream rendezvous server");
+
+        self.failed = true;
+        self.response_received();
+    }
+
+    /// Retries once all registrations in flight are answered and one of them
+    /// failed.
+    fn response_received(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        if self.in_flight > 0 {
+            return;
+        }
+
+        if self.failed {
+            self.failed = false;
+            self.retry();
+        } else {
+            self.backoff = INITIAL_BACKOFF;
+        }
+    }
+
+    fn register(&mut self, swarm: &mut Swarm<Behaviour>) {
+        tracing::info!(address=%self.address, namespaces=self.namespaces.len(), "Sending registrations to upstream rendezvous server");
+
+        for namespace in &self.namespaces {
+            swarm
+                .behaviour_mut()
+                .rendezvous
+                .register(namespace.clone(), self.peer, None);
+        }
+        self.in_flight = self.namespaces.len();
+        self.failed = false;
+    }
+
+    /// Schedules the next attempt after the backoff and doubles it.
+    fn retry(&mut self) {
+        tracing::info!(address=%self.address, retry_secs=self.backoff.as_secs(), "Retrying upstream registration");
+
+        self.schedule(Instant::now() + self.backoff);
+        self.backoff = cmp::min(self.backoff * 2, MAX_BACKOFF);
+    }
+
+    fn schedule(&mut self, at: Instant) {
+        self.deadline = Some(self.deadline.map_or(at, |deadline| cmp::min(deadline, at)));
+    }
+}
@@ -0,0 +1,201 @@
+use futures::StreamExt;
+use libp2p::multiaddr::Protocol;
+use libp2p::rendezvous::{Config, Event, Namespace, Registration, Rendezvous};
+use libp2p::swarm::{AddressScore, SwarmBuilder, SwarmEvent};
+use libp2p::{identity, Multiaddr, PeerId, Swarm};
+use rendezvous_server::{
+    create_transport, Muxer, NoisePattern, RendezvousServer, ServerConfig, ServerEvent,
+    TcpSettings, Transports, YamuxSettings,
+};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const NAMESPACE: &str = "rendezvous-server-test";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// each test runs its own server, memory ports are shared by all tests
+const SERVER_MEMORY_PORT: u64 = 1;
+const EXPIRING_SERVER_MEMORY_PORT: u64 = 2;
+
+/// TTL of the registration in [`registration_expires_after_ttl`]
+const SHORT_TTL: u64 = 2;
+
+#[tokio::test]
+async fn discover_returns_registered_record() {
+    let (events_sender, mut events) = mpsc::channel(16);
+    let server_address = Multiaddr::from(Protocol::Memory(SERVER_MEMORY_PORT));
+
+    let mut config = ServerConfig::new(identity::Keypair::generate_ed25519());
+    config.listen_memory = Some(SERVER_MEMORY_PORT);
+    config.summary_interval = None;
+    config.events = Some(events_sender);
+
+    let server = RendezvousServer::new(config).await.unwrap();
+    let server_peer_id = *server.local_peer_id();
+    tokio::spawn(server.run());
+
+    let mut client = new_client();
+    let client_peer_id = *client.local_peer_id();
+    // registrations carry the external addresses of the client, it does not
+    // have to listen on them
+    let client_address = "/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap();
+    client.add_external_address(client_address.clone(), AddressScore::Infinite);
+
+    let registrations = tokio::time::timeout(
+        TIMEOUT,
+        register_and_discover(&mut client, server_peer_id, server_address),
+    )
+    .await
+    .expect("client to register and discover before the timeout");
+
+    assert_eq!(registrations.len(), 1);
+    let registration = &registrations[0];
+    assert_eq!(registration.namespace, namespace());
+    assert_eq!(registration.record.peer_id(), client_peer_id);
+    assert_eq!(registration.record.addresses(), &[client_address.clone()]);
+
+    let event = tokio::time::timeout(TIMEOUT, events.recv())
+        .await
+        .expect("server to emit an event before the timeout")
+        .expect("server to keep the event sender");
+    assert!(
+        matches!(
+            &event,
+            ServerEvent::PeerRegistered { peer, namespace, addresses, .. }
+                if *peer == client_peer_id && namespace == NAMESPACE && addresses == &[client_address]
+        ),
+        "unexpected event {:?}",
+        event
+    );
+}
+
+#[tokio::test]
+async fn registration_expires_after_ttl() {
+    let (events_sender, mut events) = mpsc::channel(16);
+    let server_address = Multiaddr::from(Protocol::Memory(EXPIRING_SERVER_MEMORY_PORT));
+
+    let mut config = ServerConfig::new(identity::Keypair::generate_ed25519());
+    config.listen_memory = Some(EXPIRING_SERVER_MEMORY_PORT);
+    config.summary_interval = None;
+    config.min_ttl = Some(1);
+    config.events = Some(events_sender);
+
+    let server = RendezvousServer::new(config).await.unwrap();
+    let server_peer_id = *server.local_peer_id();
+    tokio::spawn(server.run());
+
+    let mut client = new_client();
+    let client_peer_id = *client.local_peer_id();
+    client.add_external_address(
+        "/ip4/127.0.0.1/tcp/4001".parse().unwrap(),
+        AddressScore::Infinite,
+    );
+
+    tokio::time::timeout(
+        TIMEOUT,
+        register(&mut client, server_peer_id, server_address, Some(SHORT_TTL)),
+    )
+    .await
+    .expect("client to register before the timeout");
+
+    let expired = tokio::time::timeout(Duration::from_secs(SHORT_TTL) + TIMEOUT, async {
+        loop {
+            match events
+                .recv()
+                .await
+                .expect("server to keep the event sender")
+            {
+                ServerEvent::RegistrationExpired {
+                    peer, namespace, ..
+                } => return (peer, namespace),
+                ServerEvent::PeerRegistered { .. } => {}
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+    })
+    .await
+    .expect("registration to expire before the timeout");
+
+    assert_eq!(expired, (client_peer_id, NAMESPACE.to_owned()));
+}
+
+/// Connects to the server and registers in [`NAMESPACE`] with `ttl`.
+async fn register(
+    client: &mut Swarm<Rendezvous>,
+    server: PeerId,
+    address: Multiaddr,
+    ttl: Option<u64>,
+) {
+    client.dial_addr(address.clone()).unwrap();
+
+    loop {
+        match client.select_next_some().await {
+            SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == server => {
+                client.behaviour_mut().register(namespace(), server, ttl);
+            }
+            SwarmEvent::UnknownPeerUnreachableAddr { .. } => {
+                // the server task might not be listening yet
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                client.dial_addr(address.clone()).unwrap();
+            }
+            SwarmEvent::Behaviour(Event::Registered { .. }) => return,
+            _ => {}
+        }
+    }
+}
+
+/// Registers in [`NAMESPACE`] and returns the registrations discovered in it
+/// afterwards.
+async fn register_and_discover(
+    client: &mut Swarm<Rendezvous>,
+    server: PeerId,
+    address: Multiaddr,
+) -> Vec<Registration> {
+    register(client, server, address, None).await;
+    client
+        .behaviour_mut()
+        .discover(Some(namespace()), None, None, server);
+
+    loop {
+        if let SwarmEvent::Behaviour(Event::Discovered { registrations, .. }) =
+            client.select_next_some().await
+        {
+            return registrations;
+        }
+    }
+}
+
+fn new_client() -> Swarm<Rendezvous> {
+    let identity = identity::Keypair::generate_ed25519();
+    let peer_id = identity.public().into_peer_id();
+
+    let transport = create_transport(&identity, Transports {
+        tcp: false,
+        websocket: false,
+        memory: true,
+        tls: None,
+        dns: false,
+        noise_pattern: NoisePattern::Xx,
+        handshake_timeout: Duration::from_secs(20),
+        dial_timeout: None,
+        muxer: Muxer::Both,
+        yamux: YamuxSettings::default(),
+        tcp_settings: TcpSettings::default(),
+        denied_cidrs: Vec::new(),
+    })
+    .unwrap();
+
+    SwarmBuilder::new(
+        transport,
+        Rendezvous::new(identity, Config::default()),
+        peer_id,
+    )
+    .executor(Box::new(|f| {
+        tokio::spawn(f);
+    }))
+    .build()
+}
+
+fn namespace() -> Namespace {
+    Namespace::new(NAMESPACE.to_owned()).unwrap()
+}